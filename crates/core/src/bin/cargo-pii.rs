@@ -0,0 +1,219 @@
+use clap::{Args, Parser, Subcommand};
+use pii_masker::cargo::CargoWorkspace;
+use pii_masker::cli::{known_fakes, or_exit, print_errors, refuse_uncommitted, FakeSettings};
+use pii_masker::config::{self, Config};
+use pii_masker::masker::MaskContext;
+use pii_masker::rust::Rust;
+use pii_masker::workspace::Workspace;
+use pii_masker::{diff, output};
+use std::path::{Path, PathBuf};
+
+/// Invoked by cargo as `cargo-pii pii <ARGS>` when running `cargo pii <ARGS>`.
+#[derive(Parser, Debug)]
+#[command(name = "cargo", bin_name = "cargo")]
+enum CargoArgs {
+    /// Masks PII in the tests of every member of a Cargo workspace
+    Pii(PiiArgs),
+}
+
+#[derive(Args, Debug)]
+struct PiiArgs {
+    /// Path to the Cargo.toml of the workspace [default: the one of the current directory]
+    #[arg(long, global = true)]
+    manifest_path: Option<PathBuf>,
+
+    /// Also handle fixtures matching this glob (relative to the workspace root, repeatable)
+    #[arg(long, global = true)]
+    fixture_glob: Vec<String>,
+
+    /// Only handle values of this category (repeatable) [default: all]
+    #[arg(long = "category", global = true)]
+    categories: Vec<String>,
+
+    /// Seed the fake value generator to get the same fake values on every run
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Derive every fake value from the key in this file and the original value
+    /// [default: $PII_MASKER_KEY]
+    #[arg(long, global = true)]
+    key_file: Option<PathBuf>,
+
+    /// Shape the fake SSNs, emails, phone and card numbers, zip codes and decimals like
+    /// the original values
+    #[arg(long, global = true)]
+    format_preserving: bool,
+
+    #[command(subcommand)]
+    command: PiiCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum PiiCommand {
+    /// List the unmasked PII found in the tests of the workspace
+    Scan,
+    /// Like scan, but exit with a non-zero status when unmasked PII is found
+    Check,
+    /// Mask the tests and fixtures of the workspace in place
+    Mask {
+        /// Print a unified diff of every file that would change and write nothing
+        #[arg(long, visible_alias = "diff")]
+        dry_run: bool,
+
+        /// Overwrite files even if they have uncommitted git changes
+        #[arg(long)]
+        force: bool,
+    },
+    /// List the workspace members and the dependencies declaring annotations
+    Members,
+}
+
+fn main() {
+    let CargoArgs::Pii(args) = CargoArgs::parse();
+    let cargo = or_exit(
+        CargoWorkspace::load(args.manifest_path.as_deref()),
+        "reading cargo metadata",
+    );
+    let config = or_exit(Config::find(&cargo.root), "reading configuration").unwrap_or_default();
+
+    match &args.command {
+        PiiCommand::Members => members(&cargo),
+        PiiCommand::Scan => run(&args, &config, &cargo, |workspace, context| {
+            scan(workspace, context, false)
+        }),
+        PiiCommand::Check => run(&args, &config, &cargo, |workspace, context| {
+            scan(workspace, context, true)
+        }),
+        PiiCommand::Mask { dry_run, force } => run(&args, &config, &cargo, |workspace, context| {
+            mask(
+                workspace,
+                context,
+                &config.fakes_path(&workspace.root),
+                *dry_run,
+                *force,
+            )
+        }),
+    }
+}
+
+/// Discovers the files of the workspace and runs `command` on them.
+fn run(
+    args: &PiiArgs,
+    config: &Config,
+    cargo: &CargoWorkspace,
+    command: impl FnOnce(&Workspace, &MaskContext),
+) {
+    let fixture_globs = if args.fixture_glob.is_empty() {
+        &config.fixtures.globs
+    } else {
+        &args.fixture_glob
+    };
+    let workspace = or_exit(cargo.workspace(fixture_globs), "discovering files");
+    let settings = args.settings();
+    let word_pool = settings.word_pool(config);
+    let mut context = settings.context(config, &word_pool);
+    known_fakes(&mut context, config, &workspace.root);
+    command(&workspace, &context);
+}
+
+fn members(cargo: &CargoWorkspace) {
+    for member in &cargo.members {
+        let marker = if member.uses_derive {
+            " (uses derive)"
+        } else {
+            ""
+        };
+        println!("{}{}", member.name, marker);
+        for dir in &member.dirs {
+            println!("  {}", dir.display());
+        }
+    }
+    for dependency in &cargo.annotated_dependencies {
+        println!("{} (dependency, annotations only)", dependency.name);
+    }
+}
+
+fn scan(workspace: &Workspace, context: &MaskContext, fail_on_findings: bool) {
+    let findings = or_exit(
+        workspace.check::<Rust, _>(Rust::Rs, context),
+        "checking workspace",
+    );
+    print_errors(workspace);
+    for finding in &findings {
+        let path = finding
+            .path
+            .strip_prefix(&workspace.root)
+            .unwrap_or(&finding.path);
+        println!(
+            "{}",
+            pii_masker::check::Finding {
+                path: path.to_path_buf(),
+                ..finding.clone()
+            }
+        );
+    }
+    eprintln!(
+        "Found {} unmasked value(s) in {} source file(s)",
+        findings.len(),
+        workspace.sources.len()
+    );
+    if fail_on_findings && !findings.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Masks the workspace in place, recording the fake values written in `fakes` so
+/// `check` does not report them.
+fn mask(workspace: &Workspace, context: &MaskContext, fakes: &Path, dry_run: bool, force: bool) {
+    let masking = or_exit(workspace.mask::<Rust, _>(Rust::Rs, context), "masking PII");
+    print_errors(workspace);
+    let files: Vec<_> = masking.files().collect();
+
+    if dry_run {
+        for file in &files {
+            let path = file
+                .path
+                .strip_prefix(&workspace.root)
+                .unwrap_or(&file.path);
+            print!(
+                "{}",
+                diff::unified_diff(&path.to_string_lossy(), &file.original, &file.masked)
+            );
+        }
+        return;
+    }
+
+    if !force {
+        let paths: Vec<PathBuf> = files.iter().map(|file| file.path.clone()).collect();
+        refuse_uncommitted(&paths);
+    }
+    for file in &files {
+        or_exit(
+            output::write_atomic(&file.path, &file.masked),
+            "writing file",
+        );
+    }
+    or_exit(
+        config::record_fakes(fakes, masking.fakes()),
+        "recording fake values",
+    );
+    let values: usize = masking
+        .sources
+        .iter()
+        .map(|file| file.replacements.len())
+        .sum();
+    println!("Masked {} value(s) in {} file(s)", values, files.len());
+}
+
+impl PiiArgs {
+    /// The fake value settings of the command line.
+    fn settings(&self) -> FakeSettings<'_> {
+        FakeSettings {
+            seed: self.seed,
+            key_file: self.key_file.as_deref(),
+            format_preserving: self.format_preserving,
+            categories: &self.categories,
+            ..FakeSettings::default()
+        }
+    }
+}
@@ -1,75 +1,1280 @@
-use clap::{arg, command, Parser};
-use pii_masker::masker::PIIMaskable;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use notify::{EventKind, RecursiveMode, Watcher};
+use pii_masker::cache::{self, Cache};
+use pii_masker::cli::{known_fakes, or_exit, print_errors, refuse_uncommitted, FakeSettings};
+use pii_masker::config::{self, Config};
+use pii_masker::envelope::Envelope;
+use pii_masker::filter::Vault;
+use pii_masker::mapping::Mapping;
+use pii_masker::masker::MaskContext;
+use pii_masker::report::Report;
 use pii_masker::rust::Rust;
+use pii_masker::vault::{self, EncryptedVault, VaultRun};
+use pii_masker::watch::WatchState;
+use pii_masker::workspace::{MaskedFile, Workspace};
+use pii_masker::{diff, filter, git, history, hook, html, lsp, output, sarif, server};
+use pii_masker_pii::similarity::FakeWordPool;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Debug;
 use std::fs;
+use std::io::{IsTerminal, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(name = "pii-masker", about = "Masks PII within source files")]
 struct PIIMaskerArgs {
+    /// Configuration file [default: the nearest pii-masker.toml above the current directory]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Number of files processed concurrently [default: one per CPU]
+    #[arg(long, global = true)]
+    jobs: Option<usize>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the annotated test values that still look like real PII
+    Scan(ScanArgs),
+    /// Like `scan`, but exit with a non-zero status when anything is found
+    Check(ScanArgs),
+    /// Replace the PII in tests and fixtures with fake values
+    Mask(Box<MaskArgs>),
+    /// Classify a value and show its similarity to each PII category
+    Classify(ClassifyArgs),
+    /// List the structs and fields annotated with `#[pii_mask]`
+    Inventory(SelectionArgs),
+    /// Put the original values back using a mapping saved by `mask --mapping-out`
+    Restore(RestoreArgs),
+    /// Run as a git hook
+    #[command(subcommand)]
+    Hook(HookCommand),
+    /// Report the PII exposed in any past commit of a git repository
+    HistoryScan(HistoryScanArgs),
+    /// Run as a git filter driver keeping the original values in a local vault
+    #[command(subcommand)]
+    Filter(FilterCommand),
+    /// Watch a directory and mask the sources and fixtures as they change
+    Watch(WatchArgs),
+    /// Run a language server over stdio reporting unmasked PII in the editor
+    Lsp(LspArgs),
+    /// Serve masking, classification and fake values over HTTP on localhost
+    Serve(ServeArgs),
+}
+
+#[derive(Subcommand, Debug)]
+enum HookCommand {
+    /// Check the staged changes for unmasked PII and block the commit if any is found
+    PreCommit(PreCommitArgs),
+}
+
+#[derive(Args, Debug)]
+struct ServeArgs {
+    /// Port to listen on, on 127.0.0.1 only
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+}
+
+#[derive(Args, Debug)]
+struct LspArgs {
+    #[command(flatten)]
+    selection: SelectionArgs,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+}
+
+#[derive(Args, Debug)]
+struct WatchArgs {
+    #[command(flatten)]
+    selection: SelectionArgs,
+
+    /// Mask fixtures matching this glob (relative to the root, repeatable)
+    #[arg(long)]
+    fixture_glob: Vec<String>,
+
+    /// Wait for this many milliseconds without changes before masking
+    #[arg(long, default_value_t = 300)]
+    debounce_ms: u64,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum FilterCommand {
+    /// Mask the working tree content read from stdin before git stores it
+    Clean(CleanArgs),
+    /// Put the original values back into the stored content read from stdin
+    Smudge(SmudgeArgs),
+}
+
+#[derive(Args, Debug)]
+struct CleanArgs {
+    /// Path of the file in the repository, as passed by git with `%f`
+    path: PathBuf,
+
+    /// Read the vault passphrase from this file [default: $PII_MASKER_VAULT_PASSPHRASE]
+    #[arg(long)]
+    passphrase_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+}
+
+#[derive(Args, Debug)]
+struct SmudgeArgs {
+    /// Path of the file in the repository, as passed by git with `%f`
+    path: PathBuf,
+
+    /// Read the vault passphrase from this file [default: $PII_MASKER_VAULT_PASSPHRASE]
+    #[arg(long)]
+    passphrase_file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct HistoryScanArgs {
+    /// A directory of the repository to scan [default: the current directory]
+    path: Option<PathBuf>,
+
+    /// Check fixtures matching this glob (relative to the repository, repeatable)
     #[arg(long)]
-    source_path: std::path::PathBuf,
+    fixture_glob: Vec<String>,
 
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+}
+
+#[derive(Args, Debug)]
+struct PreCommitArgs {
+    /// Mask the values found and re-stage the files instead of blocking the commit
+    #[arg(long)]
+    fix: bool,
+
+    /// Check staged fixtures matching this glob (relative to the repository, repeatable)
+    #[arg(long)]
+    fixture_glob: Vec<String>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+}
+
+#[derive(Args, Debug)]
+struct SelectionArgs {
+    /// File or directory to process [default: the Cargo workspace of the current directory]
+    path: Option<PathBuf>,
+
+    /// Only process sources matching this glob (relative to the root, repeatable)
     #[arg(long)]
-    fixture_path: std::path::PathBuf,
+    include: Vec<String>,
+
+    /// Skip files matching this glob (relative to the root, repeatable) [default: target/**]
+    #[arg(long)]
+    exclude: Vec<String>,
+}
 
+#[derive(Args, Debug)]
+struct PoolArgs {
     #[arg(long)]
     word_pool_size: Option<usize>,
+
+    /// Seed the fake value generator to get the same fake values on every run
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Generate fake values in this locale (en, fr_fr, pt_br, zh_cn, zh_tw, ja_jp, ar_sa)
+    #[arg(long)]
+    locale: Option<String>,
+
+    /// Derive every fake value from the key in this file and the original value, so
+    /// runs with the same key give the same output [default: $PII_MASKER_KEY]
+    #[arg(long)]
+    key_file: Option<PathBuf>,
+
+    /// Shape the fake SSNs, emails, phone and card numbers, zip codes and decimals like
+    /// the original values, keeping their character classes and separators
+    #[arg(long)]
+    format_preserving: bool,
+}
+
+#[derive(Args, Debug)]
+struct FilterArgs {
+    /// Only handle values of this category (repeatable) [default: all]
+    #[arg(long = "category")]
+    categories: Vec<String>,
+
+    /// Leave this value alone (repeatable)
+    #[arg(long)]
+    allow: Vec<String>,
+
+    /// Leave the values listed in this file alone, one per line
+    #[arg(long)]
+    allowlist: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ScanArgs {
+    #[command(flatten)]
+    selection: SelectionArgs,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+
+    /// How to print the findings
+    #[arg(long, value_enum, default_value_t = ScanFormat::Text)]
+    format: ScanFormat,
+
+    /// Reuse the results cached in `.pii-masker/cache`, and cache those of the files
+    /// that changed, instead of checking every file without writing anything
+    #[arg(long)]
+    cache: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ScanFormat {
+    /// One `file:line:col` line per finding
+    Text,
+    /// A SARIF 2.1.0 log, for code scanning dashboards
+    Sarif,
+}
+
+#[derive(Args, Debug)]
+struct MaskArgs {
+    #[command(flatten)]
+    selection: SelectionArgs,
+
+    /// Mask a single source file instead of a directory, `-` to read it from stdin and
+    /// print the masked source on stdout
+    #[arg(long, conflicts_with = "path")]
+    source_path: Option<PathBuf>,
+
+    /// A fixture to mask along with `--source-path` (repeatable)
+    #[arg(long, requires = "source_path")]
+    fixture_path: Vec<PathBuf>,
+
+    /// Read a JSON envelope `{"source": ..., "fixtures": [...]}` from stdin and print
+    /// the masked envelope on stdout
+    #[arg(long, conflicts_with_all = ["path", "source_path"])]
+    envelope: bool,
+
+    /// Mask fixtures matching this glob (relative to the root, repeatable)
+    #[arg(long, conflicts_with = "source_path")]
+    fixture_glob: Vec<String>,
+
+    /// Also mask the fixtures referenced from the tests with `include_str!`,
+    /// `fs::read_to_string` or `Path::new`
+    #[arg(long)]
+    discover_fixtures: bool,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+
+    /// Mask every file again instead of reusing the results cached in `.pii-masker/cache`
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Save the original and fake values of an --in-place run to this file, for `restore`
+    #[arg(long)]
+    mapping_out: Option<PathBuf>,
+
+    /// Record the original and fake values of the run in this encrypted vault, for
+    /// `restore --vault` (in-place runs only)
+    #[arg(long)]
+    vault: Option<PathBuf>,
+
+    /// Read the vault passphrase from this file [default: $PII_MASKER_VAULT_PASSPHRASE]
+    #[arg(long)]
+    passphrase_file: Option<PathBuf>,
+
+    /// Write a JSON report of every replacement to this file
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// List SHA-256 digests instead of the original values in the report
+    #[arg(long)]
+    hash_originals: bool,
+
+    /// Write a self-contained HTML audit report to this file
+    #[arg(long)]
+    html_report: Option<PathBuf>,
+
+    /// Number of leading characters of the original values shown in the HTML report [default: 2]
+    #[arg(long)]
+    redact_prefix: Option<usize>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+struct OutputArgs {
+    /// Overwrite the source and fixture files instead of writing `.masked` siblings
+    #[arg(long)]
+    in_place: bool,
+
+    /// Keep a copy of every overwritten file with this suffix appended (e.g. `.orig`)
+    #[arg(long)]
+    backup_suffix: Option<String>,
+
+    /// Overwrite files even if they have uncommitted git changes
+    #[arg(long)]
+    force: bool,
+
+    /// Print a unified diff of every file that would change and write nothing
+    #[arg(long, visible_alias = "diff")]
+    dry_run: bool,
+
+    /// Also write the changes as a patch that can be applied with `git apply`
+    #[arg(long)]
+    patch_out: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ClassifyArgs {
+    /// The value to classify
+    value: String,
+
+    /// Number of similar fake values to show
+    #[arg(long, default_value_t = 5)]
+    top: usize,
+
+    #[command(flatten)]
+    pool: PoolArgs,
+}
+
+#[derive(Args, Debug)]
+struct RestoreArgs {
+    /// Mapping file written by `mask --mapping-out`
+    #[arg(long, required_unless_present = "vault")]
+    mapping: Option<PathBuf>,
+
+    /// Restore the last run recorded in this vault by `mask --vault`, and remove it
+    /// from the vault
+    #[arg(long, conflicts_with = "mapping")]
+    vault: Option<PathBuf>,
+
+    /// Read the vault passphrase from this file [default: $PII_MASKER_VAULT_PASSPHRASE]
+    #[arg(long, requires = "vault")]
+    passphrase_file: Option<PathBuf>,
+
+    /// Keep a copy of every restored file with this suffix appended (e.g. `.masked`)
+    #[arg(long)]
+    backup_suffix: Option<String>,
+
+    /// Print a unified diff of every file that would change and write nothing
+    #[arg(long, visible_alias = "diff")]
+    dry_run: bool,
 }
 
 fn main() {
     let args = PIIMaskerArgs::parse();
+    let config = load_config(args.config.as_deref());
+    if let Some(jobs) = args.jobs {
+        or_exit(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(jobs)
+                .build_global(),
+            "starting worker pool",
+        );
+    }
 
-    let source_content = match fs::read_to_string(&args.source_path) {
-        Ok(content) => content,
-        Err(err) => {
-            eprintln!("Error reading source file: {}", err);
-            std::process::exit(1);
+    match &args.command {
+        Command::Scan(scan_args) => scan(scan_args, &config, false),
+        Command::Check(scan_args) => scan(scan_args, &config, true),
+        Command::Mask(mask_args) => mask(mask_args, &config),
+        Command::Classify(classify_args) => classify(classify_args, &config),
+        Command::Inventory(selection) => inventory(selection, &config),
+        Command::Restore(restore_args) => restore(restore_args),
+        Command::Hook(HookCommand::PreCommit(pre_commit_args)) => {
+            pre_commit(pre_commit_args, &config)
+        }
+        Command::HistoryScan(history_scan_args) => history_scan(history_scan_args, &config),
+        Command::Filter(FilterCommand::Clean(clean_args)) => clean(clean_args, &config),
+        Command::Filter(FilterCommand::Smudge(smudge_args)) => smudge(smudge_args, &config),
+        Command::Watch(watch_args) => watch(watch_args, &config),
+        Command::Lsp(lsp_args) => lsp(lsp_args, &config),
+        Command::Serve(serve_args) => serve(serve_args, &config),
+    }
+}
+
+/// Loads the configuration given with `--config`, or else the nearest
+/// `pii-masker.toml`, falling back to the defaults when there is none.
+fn load_config(path: Option<&Path>) -> Config {
+    let config = match path {
+        Some(path) => Config::load(path).map(Some),
+        None => {
+            let cwd = or_exit(std::env::current_dir(), "reading current directory");
+            Config::find(&cwd)
         }
     };
-    let fixture_content = match fs::read_to_string(&args.fixture_path) {
-        Ok(content) => content,
-        Err(err) => {
-            eprintln!("Error reading fixture file: {}", err);
+    or_exit(config, "reading configuration").unwrap_or_default()
+}
+
+fn scan(args: &ScanArgs, config: &Config, fail_on_findings: bool) {
+    let workspace = args.selection.discover(config, &[]);
+    let workspace = with_cache(workspace, config, &args.pool, !args.cache);
+
+    let word_pool = args.pool.word_pool(config);
+    let mut context = args.filter.context(config, &args.pool, &word_pool);
+    known_fakes(&mut context, config, &workspace.root);
+    let findings = or_exit(
+        workspace.check::<Rust, _>(Rust::Rs, &context),
+        "checking PII",
+    );
+    print_errors(&workspace);
+    print_cache_stats(&workspace);
+
+    match args.format {
+        ScanFormat::Text => {
+            for finding in &findings {
+                println!("{}", finding);
+            }
+        }
+        ScanFormat::Sarif => {
+            let log = sarif::sarif_log(&findings, &workspace.root);
+            println!("{:#}", log);
+        }
+    }
+    if findings.is_empty() {
+        eprintln!(
+            "No unmasked PII found in {} source(s)",
+            workspace.sources.len()
+        );
+    } else {
+        eprintln!(
+            "Found {} unmasked value(s) in {} source(s)",
+            findings.len(),
+            workspace.sources.len()
+        );
+        if fail_on_findings {
             std::process::exit(1);
         }
+    }
+}
+
+fn mask(args: &MaskArgs, config: &Config) {
+    let stdin_source = args.source_path.as_deref() == Some(Path::new("-"));
+    if args.envelope || stdin_source {
+        mask_stream(args, config, stdin_source);
+        return;
+    }
+
+    let workspace = match &args.source_path {
+        Some(source_path) => {
+            Workspace::from_paths(vec![source_path.clone()], args.fixture_path.clone())
+        }
+        None => {
+            let fixture_globs = if args.fixture_glob.is_empty() {
+                &config.fixtures.globs
+            } else {
+                &args.fixture_glob
+            };
+            or_exit(
+                args.selection
+                    .discover(config, fixture_globs)
+                    .with_fixture_mappings(&config.fixtures.mappings),
+                "reading fixture mappings",
+            )
+        }
+    };
+
+    let workspace = if args.discover_fixtures || config.fixtures.discover {
+        or_exit(
+            workspace.with_discovered_fixtures::<Rust, _>(&Rust::Rs),
+            "discovering fixtures",
+        )
+    } else {
+        workspace
     };
+    // a single file given on the command line is not worth caching
+    // a dry run writes nothing, not even to the cache
+    let no_cache = args.no_cache || args.source_path.is_some() || args.output.dry_run;
+    let workspace = with_cache(workspace, config, &args.pool, no_cache);
 
-    let masked = Rust::mask_tests(
-        Rust::Rs,
-        &source_content,
-        &fixture_content,
-        args.word_pool_size.unwrap_or(10000),
-    );
-
-    // write the masked content to a file
-    match masked {
-        Ok((masked_content, masked_fixture)) => {
-            let source_file_ext: String = args
-                .source_path
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .map(|ext| ext.to_string())
-                .unwrap();
-            let masked_source_file = args
-                .source_path
-                .with_extension(format!("{}.masked", source_file_ext));
-            let fixture_file_ext: String = args
-                .fixture_path
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .map(|ext| ext.to_string())
-                .unwrap();
-            let masked_fixture_file = args
-                .fixture_path
-                .with_extension(format!("{}.masked", fixture_file_ext));
-            fs::write(masked_source_file, masked_content)
-                .expect("Unable to write masked content to file");
-            fs::write(masked_fixture_file, masked_fixture)
-                .expect("Unable to write masked fixture content to file");
-        }
-        Err(err) => {
-            eprintln!("Error masking PII: {:?}", err);
+    let word_pool = args.pool.word_pool(config);
+    let mut context = args.filter.context(config, &args.pool, &word_pool);
+    known_fakes(&mut context, config, &workspace.root);
+    let masking = or_exit(workspace.mask::<Rust, _>(Rust::Rs, &context), "masking PII");
+    print_errors(&workspace);
+    print_cache_stats(&workspace);
+
+    let output = OutputArgs {
+        in_place: args.output.in_place || config.output.in_place,
+        backup_suffix: args
+            .output
+            .backup_suffix
+            .clone()
+            .or_else(|| config.output.backup_suffix.clone()),
+        force: args.output.force,
+        dry_run: args.output.dry_run,
+        patch_out: args.output.patch_out.clone(),
+    };
+    let files: Vec<_> = masking
+        .files()
+        .map(|file| (file.path.as_path(), file.masked.as_str()))
+        .collect();
+    write_files(&output, &workspace.root, &files);
+    if output.in_place && !output.dry_run && !files.is_empty() {
+        // the fake values are committed along with the files, so `check` accepts them
+        or_exit(
+            config::record_fakes(&config.fakes_path(&workspace.root), masking.fakes()),
+            "recording fake values",
+        );
+    }
+    let mapping_out = args.mapping_out.clone().or_else(|| {
+        config
+            .output
+            .mapping
+            .as_ref()
+            .map(|mapping| config.resolve(mapping))
+    });
+    if let Some(mapping_out) = &mapping_out {
+        // `.masked` siblings leave the originals in place, there is nothing to restore
+        if !output.in_place {
+            eprintln!("Not writing the mapping: only --in-place runs can be restored");
+        } else if !output.dry_run {
+            or_exit(Mapping::from(&masking).save(mapping_out), "writing mapping");
+        }
+    }
+    let vault = args.vault.clone().or_else(|| {
+        config
+            .output
+            .vault
+            .as_ref()
+            .map(|vault| config.resolve(vault))
+    });
+    if let Some(vault) = &vault {
+        // `.masked` siblings leave the originals in place, there is nothing to restore
+        if !output.in_place {
+            eprintln!("Not recording the run in the vault: only --in-place runs can be restored");
+        } else if !output.dry_run && masking.files().next().is_some() {
+            let passphrase = passphrase(args.passphrase_file.as_deref());
+            let mut encrypted = or_exit(EncryptedVault::open(vault, &passphrase), "opening vault");
+            encrypted.runs.push(VaultRun::from(&masking));
+            or_exit(encrypted.save(vault, &passphrase), "writing vault");
+        }
+    }
+    let report = args.report.clone().or_else(|| {
+        config
+            .output
+            .report
+            .as_ref()
+            .map(|report| config.resolve(report))
+    });
+    if let Some(report) = &report {
+        let hash_originals = args.hash_originals || config.output.hash_originals;
+        or_exit(
+            Report::new(&workspace.root, &masking, hash_originals).save(report),
+            "writing report",
+        );
+    }
+    let html_report = args.html_report.clone().or_else(|| {
+        config
+            .output
+            .html_report
+            .as_ref()
+            .map(|html_report| config.resolve(html_report))
+    });
+    if let Some(html_report) = &html_report {
+        let inventory = or_exit(
+            workspace.inventory::<Rust, _>(&Rust::Rs),
+            "discovering annotations",
+        );
+        print_errors(&workspace);
+        let redact_prefix = args
+            .redact_prefix
+            .or(config.output.redact_prefix)
+            .unwrap_or(2);
+        let html = html::html_report(&workspace.root, &inventory, &masking, redact_prefix);
+        or_exit(fs::write(html_report, html), "writing HTML report");
+    }
+
+    let dry_run = args.output.dry_run;
+    let verb = if dry_run { "would mask" } else { "masked" };
+    println!(
+        "Scanned {} source(s) and {} fixture(s) under {}",
+        workspace.sources.len(),
+        workspace.fixtures.len(),
+        workspace.root.display()
+    );
+    for file in masking.files() {
+        let path = file
+            .path
+            .strip_prefix(&workspace.root)
+            .unwrap_or(&file.path);
+        println!(
+            "  {} {} ({} value(s))",
+            verb,
+            path.display(),
+            file.replacements.len()
+        );
+    }
+    let replacements: usize = masking
+        .sources
+        .iter()
+        .map(|file| file.replacements.len())
+        .sum();
+    println!(
+        "{} {} literal(s) in {} file(s)",
+        if dry_run { "Would mask" } else { "Masked" },
+        replacements,
+        masking.sources.len() + masking.fixtures.len()
+    );
+}
+
+/// Attaches the cache kept below the root of `workspace`, unless `no_cache` is set.
+/// The cache holds original values, so it is kept out of git.
+fn with_cache(workspace: Workspace, config: &Config, pool: &PoolArgs, no_cache: bool) -> Workspace {
+    if no_cache {
+        return workspace;
+    }
+    // every setting of the configuration and of the word pool may change the results
+    let settings = format!("{:?}", config);
+    let pool = format!("{:?}", pool);
+    let cache = Cache::open(&workspace.root, &[&settings, &pool]);
+    cache.prune(cache::MAX_AGE);
+    let _ = git::exclude(&workspace.root, "/.pii-masker/");
+    workspace.with_cache(cache)
+}
+
+fn print_cache_stats(workspace: &Workspace) {
+    if let Some(cache) = workspace.cache() {
+        let (hits, misses) = cache.stats();
+        eprintln!("Cache: {} hit(s), {} miss(es)", hits, misses);
+    }
+}
+
+/// Masks content read from stdin and prints the result on stdout: either a bare
+/// source, or a JSON envelope holding a source and its fixtures.
+fn mask_stream(args: &MaskArgs, config: &Config, bare_source: bool) {
+    if !args.fixture_path.is_empty() {
+        eprintln!("Error: --fixture-path cannot be used with --source-path -, use --envelope");
+        std::process::exit(1);
+    }
+
+    let stdin = std::io::stdin();
+    let envelope = if bare_source {
+        let mut source = String::new();
+        or_exit(stdin.lock().read_to_string(&mut source), "reading stdin");
+        Envelope {
+            source,
+            fixtures: vec![],
+        }
+    } else {
+        or_exit(Envelope::from_reader(stdin.lock()), "reading stdin")
+    };
+
+    let word_pool = args.pool.word_pool(config);
+    let context = args.filter.context(config, &args.pool, &word_pool);
+    let masked = or_exit(envelope.mask::<Rust, _>(Rust::Rs, &context), "masking PII");
+    if bare_source {
+        print!("{}", masked.source);
+    } else {
+        println!("{}", or_exit(masked.to_json(), "writing stdout"));
+    }
+}
+
+fn classify(args: &ClassifyArgs, config: &Config) {
+    let word_pool = args.pool.word_pool(config);
+    let classification = or_exit(
+        pii_masker_pii::similarity::classify_word(&args.value, &word_pool, args.top),
+        "classifying value",
+    );
+
+    println!("category: {}", classification.category);
+    for (category, score) in &classification.scores {
+        println!("  {:<18} {:.3}", category.to_string(), score);
+    }
+    println!("similar: {}", classification.similar.join(", "));
+}
+
+fn inventory(args: &SelectionArgs, config: &Config) {
+    let workspace = args.discover(config, &[]);
+    let inventory = or_exit(
+        workspace.inventory::<Rust, _>(&Rust::Rs),
+        "discovering annotations",
+    );
+    print_errors(&workspace);
+
+    for (path, annotations) in &inventory {
+        let path = path.strip_prefix(&workspace.root).unwrap_or(path);
+        println!("{}", path.display());
+        for annotation in annotations {
+            print!(
+                "  {}.{}: {}",
+                annotation.struct_name(),
+                annotation.field(),
+                annotation.faker()
+            );
+            match annotation.format() {
+                Some(format) => println!(" (format: {})", format),
+                None => println!(),
+            }
+        }
+    }
+    let fields: usize = inventory
+        .iter()
+        .map(|(_, annotations)| annotations.len())
+        .sum();
+    eprintln!(
+        "Found {} annotated field(s) in {} source(s)",
+        fields,
+        inventory.len()
+    );
+}
+
+fn restore(args: &RestoreArgs) {
+    let mut restored = vec![];
+    if let Some(vault) = &args.vault {
+        let passphrase = passphrase(args.passphrase_file.as_deref());
+        let mut encrypted = or_exit(EncryptedVault::open(vault, &passphrase), "opening vault");
+        let Some(run) = encrypted.runs.pop() else {
+            eprintln!("Error: no masking run recorded in {}", vault.display());
             std::process::exit(1);
+        };
+        for file in &run.files {
+            let content = or_exit(fs::read_to_string(&file.path), "reading masked file");
+            restored.push((
+                file.path.clone(),
+                or_exit(file.restore(&content), "restoring"),
+            ));
+        }
+        if !args.dry_run {
+            or_exit(encrypted.save(vault, &passphrase), "writing vault");
+        }
+    } else if let Some(mapping) = &args.mapping {
+        let mapping = or_exit(Mapping::load(mapping), "reading mapping");
+        for file in &mapping.files {
+            let content = or_exit(fs::read_to_string(&file.path), "reading masked file");
+            restored.push((
+                file.path.clone(),
+                or_exit(file.restore(&content), "restoring"),
+            ));
+        }
+    }
+
+    let files: Vec<_> = restored
+        .iter()
+        .map(|(path, content)| (path.as_path(), content.as_str()))
+        .collect();
+    let output = OutputArgs {
+        in_place: true,
+        backup_suffix: args.backup_suffix.clone(),
+        force: true,
+        dry_run: args.dry_run,
+        patch_out: None,
+    };
+    let cwd = or_exit(std::env::current_dir(), "reading current directory");
+    write_files(&output, &cwd, &files);
+
+    let verb = if args.dry_run {
+        "Would restore"
+    } else {
+        "Restored"
+    };
+    println!("{} {} file(s)", verb, files.len());
+}
+
+fn pre_commit(args: &PreCommitArgs, config: &Config) {
+    let cwd = or_exit(std::env::current_dir(), "reading current directory");
+    let Some(repo) = git::toplevel(&cwd) else {
+        eprintln!("Error: {} is not inside a git repository", cwd.display());
+        std::process::exit(1);
+    };
+    let fixture_globs = if args.fixture_glob.is_empty() {
+        &config.fixtures.globs
+    } else {
+        &args.fixture_glob
+    };
+
+    let word_pool = args.pool.word_pool(config);
+    let mut context = args.filter.context(config, &args.pool, &word_pool);
+    known_fakes(&mut context, config, &repo);
+    let result = or_exit(
+        hook::pre_commit::<Rust, _>(Rust::Rs, &repo, fixture_globs, &context, args.fix),
+        "checking staged changes",
+    );
+    if result.findings.is_empty() {
+        return;
+    }
+
+    if !args.fix {
+        for finding in &result.findings {
+            println!("{}", finding);
+        }
+        eprintln!(
+            "Commit blocked: {} unmasked value(s) in the staged changes, \
+             run `pii-masker hook pre-commit --fix` to mask them",
+            result.findings.len()
+        );
+        std::process::exit(1);
+    }
+
+    let mut paths: Vec<PathBuf> = result.masked.iter().map(|file| file.path.clone()).collect();
+    for file in &result.masked {
+        or_exit(
+            output::write_atomic(&file.path, &file.masked),
+            "writing file",
+        );
+        let path = file.path.strip_prefix(&repo).unwrap_or(&file.path);
+        println!(
+            "  masked {} ({} value(s))",
+            path.display(),
+            file.replacements.len()
+        );
+    }
+    if !paths.is_empty() {
+        // the fake values are committed along with the files, so `check` accepts them
+        let fakes = config.fakes_path(&repo);
+        or_exit(
+            config::record_fakes(&fakes, result.masked.iter().flat_map(MaskedFile::fakes)),
+            "recording fake values",
+        );
+        paths.push(fakes);
+        or_exit(git::add(&repo, &paths), "staging masked files");
+    }
+
+    if !result.partially_staged.is_empty() {
+        eprintln!("Commit blocked: these files also have unstaged changes, mask them by hand:");
+        for path in &result.partially_staged {
+            eprintln!("  {}", path.strip_prefix(&repo).unwrap_or(path).display());
         }
+        std::process::exit(1);
+    }
+}
+
+fn serve(args: &ServeArgs, config: &Config) {
+    let word_pool = args.pool.word_pool(config);
+    let context = args.filter.context(config, &args.pool, &word_pool);
+    let service = server::Service::<Rust, _>::new(Rust::Rs, &context);
+    eprintln!("Listening on http://127.0.0.1:{}", args.port);
+    or_exit(server::serve(&service, args.port), "serving");
+}
+
+fn lsp(args: &LspArgs, config: &Config) {
+    let workspace = args.selection.discover(config, &[]);
+    let word_pool = args.pool.word_pool(config);
+    let mut context = args.filter.context(config, &args.pool, &word_pool);
+    known_fakes(&mut context, config, &workspace.root);
+    let fakes_path = config.fakes_path(&workspace.root);
+    let session = or_exit(
+        lsp::Session::<Rust, _>::new(Rust::Rs, context, workspace),
+        "reading annotations",
+    );
+    or_exit(
+        lsp::run(session.with_fakes_path(fakes_path)),
+        "running language server",
+    );
+}
+
+fn watch(args: &WatchArgs, config: &Config) {
+    let fixture_globs = if args.fixture_glob.is_empty() {
+        &config.fixtures.globs
+    } else {
+        &args.fixture_glob
+    };
+    let root = args.selection.discover(config, fixture_globs).root;
+    let word_pool = args.pool.word_pool(config);
+    let mut context = args.filter.context(config, &args.pool, &word_pool);
+    known_fakes(&mut context, config, &root);
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = or_exit(notify::recommended_watcher(sender), "starting watcher");
+    or_exit(
+        watcher.watch(&root, RecursiveMode::Recursive),
+        "watching directory",
+    );
+    eprintln!("Watching {}", root.display());
+
+    let debounce = Duration::from_millis(args.debounce_ms);
+    let mut state = WatchState::default();
+    let mut paths = BTreeSet::new();
+    loop {
+        let event = if paths.is_empty() {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            receiver.recv_timeout(debounce)
+        };
+        match event {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    paths.extend(event.paths);
+                }
+                continue;
+            }
+            Ok(Err(err)) => {
+                eprintln!("Error watching {}: {}", root.display(), err);
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let changed = state.changed_by_others(&std::mem::take(&mut paths));
+        let workspace = args.selection.discover(config, fixture_globs);
+        let files: HashSet<PathBuf> = workspace
+            .sources
+            .iter()
+            .chain(&workspace.fixtures)
+            .filter_map(|file| fs::canonicalize(file).ok())
+            .collect();
+        let relevant: BTreeSet<PathBuf> = changed
+            .into_iter()
+            .filter(|path| fs::canonicalize(path).is_ok_and(|path| files.contains(&path)))
+            .collect();
+        if relevant.is_empty() {
+            continue;
+        }
+
+        let masked = state
+            .mask_changed::<Rust, _>(&workspace, &relevant, Rust::Rs, &mut context)
+            .and_then(|masking| state.write(&masking).map(|_| masking))
+            .and_then(|masking| {
+                config::record_fakes(&config.fakes_path(&root), masking.fakes())?;
+                Ok(masking)
+            });
+        match masked {
+            Ok(masking) => {
+                let values: usize = masking.files().map(|file| file.replacements.len()).sum();
+                println!(
+                    "{} changed file(s): masked {} value(s), wrote {} file(s)",
+                    relevant.len(),
+                    values,
+                    masking.files().count()
+                );
+            }
+            Err(err) => println!(
+                "{} changed file(s): error masking PII: {:?}",
+                relevant.len(),
+                err
+            ),
+        }
+    }
+}
+
+/// Returns the repository the filter runs in, and `path` relative to its root.
+fn filter_target(path: &Path) -> (PathBuf, PathBuf) {
+    let cwd = or_exit(std::env::current_dir(), "reading current directory");
+    let Some(repo) = git::toplevel(&cwd) else {
+        eprintln!("Error: {} is not inside a git repository", cwd.display());
+        std::process::exit(1);
+    };
+    let absolute = cwd.join(path);
+    let relative = absolute.strip_prefix(&repo).unwrap_or(path).to_path_buf();
+    (repo, relative)
+}
+
+fn clean(args: &CleanArgs, config: &Config) {
+    let (repo, path) = filter_target(&args.path);
+    let mut content = String::new();
+    or_exit(
+        std::io::stdin().lock().read_to_string(&mut content),
+        "reading stdin",
+    );
+
+    let word_pool = args.pool.word_pool(config);
+    let mut context = args.filter.context(config, &args.pool, &word_pool);
+    known_fakes(&mut context, config, &repo);
+    let mut vault = filter_vault(&repo, config, args.passphrase_file.as_deref());
+    let cleaned = or_exit(
+        filter::clean::<Rust, _>(Rust::Rs, &repo, &path, &content, &mut vault, &context),
+        "masking PII",
+    );
+    or_exit(vault.save(&repo), "writing vault");
+    // a checkout without the filter holds the stored fakes, which `check` accepts
+    let file = repo.join(&path);
+    let fakes = vault.fakes(&path).map(|fake| (file.as_path(), fake));
+    or_exit(
+        config::record_fakes(&config.fakes_path(&repo), fakes),
+        "recording fake values",
+    );
+    print!("{}", cleaned);
+}
+
+fn smudge(args: &SmudgeArgs, config: &Config) {
+    let (repo, path) = filter_target(&args.path);
+    let mut content = String::new();
+    or_exit(
+        std::io::stdin().lock().read_to_string(&mut content),
+        "reading stdin",
+    );
+
+    let vault = filter_vault(&repo, config, args.passphrase_file.as_deref());
+    print!("{}", filter::smudge(&path, &content, &vault));
+}
+
+/// Opens the vault of the git filter: the one `mask` records its runs in when the
+/// configuration sets it, or else the one in the repository.
+fn filter_vault(repo: &Path, config: &Config, passphrase_file: Option<&Path>) -> Vault {
+    let path = config
+        .output
+        .vault
+        .as_ref()
+        .map(|vault| config.resolve(vault));
+    let passphrase = passphrase(passphrase_file);
+    or_exit(
+        Vault::open(repo, path.as_deref(), &passphrase),
+        "reading vault",
+    )
+}
+
+fn history_scan(args: &HistoryScanArgs, config: &Config) {
+    let dir = match &args.path {
+        Some(path) => path.clone(),
+        None => or_exit(std::env::current_dir(), "reading current directory"),
+    };
+    let Some(repo) = git::toplevel(&dir) else {
+        eprintln!("Error: {} is not inside a git repository", dir.display());
+        std::process::exit(1);
+    };
+    let fixture_globs = if args.fixture_glob.is_empty() {
+        &config.fixtures.globs
+    } else {
+        &args.fixture_glob
+    };
+
+    let word_pool = args.pool.word_pool(config);
+    let context = args.filter.context(config, &args.pool, &word_pool);
+    let scan = or_exit(
+        history::history_scan::<Rust, _>(Rust::Rs, &repo, fixture_globs, &context),
+        "scanning history",
+    );
+    for error in &scan.errors {
+        eprintln!(
+            "Error reading {} at {}: {:?}",
+            error.path.display(),
+            &error.commit[..error.commit.len().min(12)],
+            error.error
+        );
+    }
+    let exposures = scan.exposures;
+
+    for exposure in &exposures {
+        let commits: Vec<&str> = exposure
+            .commits
+            .iter()
+            .map(|commit| &commit[..commit.len().min(12)])
+            .collect();
+        println!(
+            "{}: {}.{} = {:?} ({}{}) in {} commit(s): {}",
+            exposure.path.display(),
+            exposure.struct_name,
+            exposure.field,
+            exposure.value,
+            exposure.category,
+            if exposure.inferred { ", inferred" } else { "" },
+            commits.len(),
+            commits.join(" ")
+        );
+    }
+    let commits: HashSet<&String> = exposures
+        .iter()
+        .flat_map(|exposure| &exposure.commits)
+        .collect();
+    eprintln!(
+        "Found {} exposed value(s) in {} commit(s)",
+        exposures.len(),
+        commits.len()
+    );
+}
+
+impl SelectionArgs {
+    /// Walks the selected file or directory, defaulting to the directory of the
+    /// configuration file, or else the Cargo workspace containing the current directory.
+    /// Globs not given on the command line are taken from the configuration.
+    fn discover(&self, config: &Config, fixture_globs: &[String]) -> Workspace {
+        let root = match (&self.path, config.dir()) {
+            (Some(path), _) => path.clone(),
+            (None, Some(dir)) => dir.to_path_buf(),
+            (None, None) => {
+                let cwd = or_exit(std::env::current_dir(), "reading current directory");
+                match Workspace::find_cargo_root(&cwd) {
+                    Some(root) => root,
+                    None => {
+                        eprintln!("Error: no Cargo.toml found above {}", cwd.display());
+                        std::process::exit(1);
+                    }
+                }
+            }
+        };
+        let includes = if self.include.is_empty() {
+            &config.sources.include
+        } else {
+            &self.include
+        };
+        let default_excludes = vec!["target/**".to_string()];
+        let excludes = match (self.exclude.is_empty(), &config.sources.exclude) {
+            (false, _) => &self.exclude,
+            (true, Some(excludes)) => excludes,
+            (true, None) => &default_excludes,
+        };
+        or_exit(
+            Workspace::discover(&root, includes, excludes, fixture_globs),
+            "discovering files",
+        )
+    }
+}
+
+impl PoolArgs {
+    /// The fake value settings of the command line, with the `categories` to handle.
+    fn settings<'a>(&'a self, categories: &'a [String]) -> FakeSettings<'a> {
+        FakeSettings {
+            word_pool_size: self.word_pool_size,
+            seed: self.seed,
+            locale: self.locale.as_deref(),
+            key_file: self.key_file.as_deref(),
+            format_preserving: self.format_preserving,
+            categories,
+        }
+    }
+
+    /// Generates the fake word pool, seeded when a seed is configured.
+    fn word_pool(&self, config: &Config) -> FakeWordPool {
+        self.settings(&[]).word_pool(config)
+    }
+}
+
+impl FilterArgs {
+    /// Combines the category selection and allowlists of the command line and the
+    /// configuration. Allowlists add up, categories given on the command line replace
+    /// the configured ones. Fake values are picked with the key of `pool`, if any.
+    fn context<'a>(
+        &self,
+        config: &Config,
+        pool: &PoolArgs,
+        word_pool: &'a FakeWordPool,
+    ) -> MaskContext<'a> {
+        let mut context = pool.settings(&self.categories).context(config, word_pool);
+        context.allowlist.extend(self.allow.iter().cloned());
+        if let Some(path) = &self.allowlist {
+            context
+                .allowlist
+                .extend(or_exit(config::read_allowlist(path), "reading allowlist"));
+        }
+        context
+    }
+}
+
+/// Reads the vault passphrase from `file`, or else from `PII_MASKER_VAULT_PASSPHRASE`.
+fn passphrase(file: Option<&Path>) -> String {
+    let passphrase = match file {
+        Some(file) => or_exit(fs::read_to_string(file), "reading passphrase file"),
+        None => match std::env::var(vault::PASSPHRASE_ENV) {
+            Ok(passphrase) => passphrase,
+            Err(_) => {
+                eprintln!(
+                    "Error: the vault needs a passphrase, use --passphrase-file or set {}",
+                    vault::PASSPHRASE_ENV
+                );
+                std::process::exit(1);
+            }
+        },
+    };
+    passphrase.trim_end_matches(['\r', '\n']).to_string()
+}
+
+/// Writes the new content either to `.masked` siblings or, with `--in-place`, over
+/// the original files. In-place writes are refused as a whole if any of the files has
+/// uncommitted git changes, unless `--force` is given. Patches are labelled with paths
+/// relative to `root`.
+fn write_files(args: &OutputArgs, root: &Path, files: &[(&Path, &str)]) {
+    if args.dry_run || args.patch_out.is_some() {
+        let patch = make_patch(root, files);
+        if args.dry_run {
+            if std::io::stdout().is_terminal() {
+                print!("{}", diff::colorize(&patch));
+            } else {
+                print!("{}", patch);
+            }
+        }
+        if let Some(patch_out) = &args.patch_out {
+            or_exit(fs::write(patch_out, &patch), "writing patch file");
+        }
+        if args.dry_run {
+            return;
+        }
+    }
+
+    if !args.in_place {
+        for (path, content) in files {
+            fs::write(masked_path(path), content).expect("Unable to write masked content to file");
+        }
+        return;
+    }
+
+    if !args.force {
+        let paths: Vec<PathBuf> = files.iter().map(|(path, _)| path.to_path_buf()).collect();
+        refuse_uncommitted(&paths);
+    }
+
+    for (path, content) in files {
+        if let Some(suffix) = &args.backup_suffix {
+            or_exit(output::backup(path, suffix), "writing backup");
+        }
+        or_exit(output::write_atomic(path, content), "writing file");
+    }
+}
+
+/// Builds a patch with the changes of every file, using paths relative to `root` so it
+/// can be applied from there with `git apply` or `patch -p1`. Files outside of `root`
+/// are labelled with their path from the file system root.
+fn make_patch(root: &Path, files: &[(&Path, &str)]) -> String {
+    let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let mut patch = String::new();
+    for (path, content) in files {
+        let original = or_exit(fs::read_to_string(path), "reading file");
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let label: PathBuf = path
+            .strip_prefix(&root)
+            .unwrap_or(&path)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        patch.push_str(&diff::unified_diff(
+            &label.to_string_lossy(),
+            &original,
+            content,
+        ));
+    }
+    patch
+}
+
+/// Returns the sibling path the masked content is written to, e.g. `student.rs.masked`.
+fn masked_path(path: &Path) -> PathBuf {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => path.with_extension(format!("{}.masked", ext)),
+        None => path.with_extension("masked"),
     }
 }
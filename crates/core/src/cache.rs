@@ -0,0 +1,393 @@
+use std::fs::{self, File};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use ast_grep_core::Language;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::check::Finding;
+use crate::key::PseudonymKey;
+use crate::masker::{MaskContext, PIIMaskable, Replacement};
+use crate::output::write_atomic;
+
+/// Where the cache is kept, relative to the root of the workspace.
+pub const CACHE_DIR: &str = ".pii-masker/cache";
+
+/// How long an entry is kept without being used.
+pub const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Results of earlier runs stored on disk, one JSON file per entry, named after the
+/// digest of every input the result was computed from. Entries are never invalidated:
+/// a changed input simply leads to another entry, and the entries left unused for a
+/// while are removed by `prune`. Original values are never stored, see `Redact`.
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    /// Digest of the inputs shared by every file, e.g. the configuration.
+    settings: String,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl Cache {
+    /// Opens the cache of the workspace at `root`. `settings` are the inputs every
+    /// result depends on besides the files themselves, such as the configuration.
+    pub fn open(root: &Path, settings: &[&str]) -> Self {
+        Self {
+            dir: root.join(CACHE_DIR),
+            settings: digest(settings),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Derives the key of an entry from the shared settings and `inputs`.
+    pub fn key(&self, inputs: &[&str]) -> String {
+        let mut parts = vec![self.settings.as_str()];
+        parts.extend(inputs);
+        digest(&parts)
+    }
+
+    /// Returns the stored entry, counting a miss when there is none or it cannot be read.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.get_if(key, |_| true)
+    }
+
+    /// Returns the stored entry if `accept` takes it, counting a miss otherwise. A
+    /// returned entry counts as used, so it is not pruned.
+    pub fn get_if<T: DeserializeOwned>(
+        &self,
+        key: &str,
+        accept: impl FnOnce(&mut T) -> bool,
+    ) -> Option<T> {
+        let path = self.path(key);
+        let entry = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .and_then(|mut entry| accept(&mut entry).then_some(entry));
+        let counter = if entry.is_some() {
+            let _ = File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(SystemTime::now()));
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        entry
+    }
+
+    /// Stores an entry. The cache is best effort, so failing to write is not an error.
+    pub fn put<T: Serialize>(&self, key: &str, value: &T) {
+        let Ok(content) = serde_json::to_string(value) else {
+            return;
+        };
+        if fs::create_dir_all(&self.dir).is_ok() {
+            let _ = write_atomic(&self.path(key), &content);
+        }
+    }
+
+    /// Returns the entry under `key`, or computes and stores it.
+    pub fn get_or_insert_with<T: Serialize + DeserializeOwned, E>(
+        &self,
+        key: &str,
+        compute: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E> {
+        if let Some(value) = self.get(key) {
+            return Ok(value);
+        }
+        let value = compute()?;
+        self.put(key, &value);
+        Ok(value)
+    }
+
+    /// Returns the entry computed from `content` under `key` if `accept` takes it, with
+    /// its original values recovered from `content`.
+    pub fn get_redacted<T: DeserializeOwned + Redact>(
+        &self,
+        key: &str,
+        content: &str,
+        accept: impl FnOnce(&T) -> bool,
+    ) -> Option<T> {
+        self.get_if(key, |entry: &mut T| entry.recover(content) && accept(entry))
+    }
+
+    /// Stores an entry with its original values replaced by their digests.
+    pub fn put_redacted<T: Serialize + Redact + Clone>(&self, key: &str, value: &T) {
+        let mut redacted = value.clone();
+        redacted.redact();
+        self.put(key, &redacted);
+    }
+
+    /// Like `get_or_insert_with`, for an entry computed from `content` that holds
+    /// original values.
+    pub fn get_or_insert_redacted<T: Serialize + DeserializeOwned + Redact + Clone, E>(
+        &self,
+        key: &str,
+        content: &str,
+        compute: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E> {
+        if let Some(value) = self.get_redacted(key, content, |_| true) {
+            return Ok(value);
+        }
+        let value = compute()?;
+        self.put_redacted(key, &value);
+        Ok(value)
+    }
+
+    /// Removes the entries neither stored nor used within `max_age`.
+    pub fn prune(&self, max_age: Duration) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let stale = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > max_age);
+            if stale && path.extension().is_some_and(|ext| ext == "json") {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// The number of entries found and missed so far.
+    pub fn stats(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+/// A result holding original values found in the content it was computed from. The
+/// cache stores their digests instead, and recovers them from the same content at
+/// their ranges, so the originals are never written to disk.
+pub trait Redact {
+    /// Replaces the original values with their digests.
+    fn redact(&mut self);
+    /// Puts back the original values standing in `content` at their ranges, telling
+    /// whether every one of them matches its digest.
+    fn recover(&mut self, content: &str) -> bool;
+}
+
+/// Recovers `value`, redacted to its digest, from `content` at `range`.
+fn recover_value(value: &mut String, content: &str, range: &Range<usize>) -> bool {
+    match content.get(range.clone()) {
+        Some(original) if digest(&[original]) == *value => {
+            *value = original.to_string();
+            true
+        }
+        _ => false,
+    }
+}
+
+impl Redact for Replacement {
+    fn redact(&mut self) {
+        self.original = digest(&[&self.original]);
+    }
+
+    fn recover(&mut self, content: &str) -> bool {
+        recover_value(&mut self.original, content, &self.range)
+    }
+}
+
+impl Redact for Finding {
+    fn redact(&mut self) {
+        self.value = digest(&[&self.value]);
+    }
+
+    fn recover(&mut self, content: &str) -> bool {
+        recover_value(&mut self.value, content, &self.range)
+    }
+}
+
+impl<T: Redact> Redact for Vec<T> {
+    fn redact(&mut self) {
+        self.iter_mut().for_each(Redact::redact);
+    }
+
+    fn recover(&mut self, content: &str) -> bool {
+        self.iter_mut().all(|value| value.recover(content))
+    }
+}
+
+impl<T: Redact> Redact for Option<T> {
+    fn redact(&mut self) {
+        self.iter_mut().for_each(Redact::redact);
+    }
+
+    fn recover(&mut self, content: &str) -> bool {
+        self.iter_mut().all(|value| value.recover(content))
+    }
+}
+
+// a masked content holds no original value, only its replacements do
+impl<T: Redact> Redact for (String, T) {
+    fn redact(&mut self) {
+        self.1.redact();
+    }
+
+    fn recover(&mut self, content: &str) -> bool {
+        self.1.recover(content)
+    }
+}
+
+/// Digest of the rules matching the annotations and the test expectations, and of the
+/// version of the masker, so upgrading it invalidates the cache.
+pub fn rule_set_digest<M: PIIMaskable<L>, L: Language>(language: L) -> String {
+    let annotations = M::make_struct_annotations_rule()
+        .ok()
+        .and_then(|rule| serde_json::to_string(&rule).ok())
+        .unwrap_or_default();
+    let expectations = M::make_expectations_discovery_matcher(language)
+        .ok()
+        .and_then(|rule| serde_json::to_string(&rule.core).ok())
+        .unwrap_or_default();
+    digest(&[env!("CARGO_PKG_VERSION"), &annotations, &expectations])
+}
+
+/// Digest of what a masking context selects and generates from: the categories, the
+/// allowlist, the known fake values, the fingerprint of the key and the strategy.
+///
+/// The word pool itself is left out: a pool generated without a seed differs on every
+/// run, which would make every entry a miss. The settings the pool is generated from
+/// belong to the settings of the cache instead.
+pub fn context_digest(context: &MaskContext) -> String {
+    let mut categories: Vec<String> = context
+        .categories
+        .iter()
+        .map(|category| category.to_string())
+        .collect();
+    categories.sort();
+    let mut allowlist: Vec<&str> = context.allowlist.iter().map(String::as_str).collect();
+    allowlist.sort();
+    let mut known_fakes: Vec<String> = context
+        .known_fakes
+        .iter()
+        .flat_map(|(path, fakes)| {
+            fakes
+                .iter()
+                .map(move |fake| format!("{}\t{}", path.display(), fake))
+        })
+        .collect();
+    known_fakes.sort();
+    let key = context
+        .key
+        .as_ref()
+        .map(PseudonymKey::fingerprint)
+        .unwrap_or_default();
+    let strategy = if context.format_preserving {
+        "format-preserving"
+    } else {
+        "pool"
+    };
+    digest(&[
+        &categories.join("\n"),
+        &allowlist.join("\n"),
+        &known_fakes.join("\n"),
+        &key,
+        strategy,
+    ])
+}
+
+/// SHA-256 of the parts, each prefixed with its length so their boundaries count.
+pub fn digest(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pii_masker_pii::similarity::Category;
+
+    #[test]
+    fn test_cache() {
+        let root = std::env::temp_dir().join(format!("pii-masker-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        let cache = Cache::open(&root, &["seed = 7"]);
+        let key = cache.key(&["tests/student.rs", "email: \"jdoe@gmail.com\""]);
+        let computed: Result<Vec<String>, ()> =
+            cache.get_or_insert_with(&key, || Ok(vec!["joe@example.com".to_string()]));
+        assert_eq!(computed.unwrap(), vec!["joe@example.com"]);
+        let cached: Result<Vec<String>, ()> = cache.get_or_insert_with(&key, || Err(()));
+        assert_eq!(cached.unwrap(), vec!["joe@example.com"]);
+        assert_eq!(cache.stats(), (1, 1));
+
+        // other settings lead to other entries
+        let other = Cache::open(&root, &["seed = 8"]);
+        assert_ne!(
+            other.key(&["tests/student.rs", "email: \"jdoe@gmail.com\""]),
+            key
+        );
+        assert_ne!(digest(&["ab", "c"]), digest(&["a", "bc"]));
+
+        // an entry used recently is kept, a stale one is removed
+        let rejected: Option<Vec<String>> = cache.get_if(&key, |_: &mut Vec<String>| false);
+        assert!(rejected.is_none());
+        cache.prune(MAX_AGE);
+        assert!(cache.get::<Vec<String>>(&key).is_some());
+        std::thread::sleep(Duration::from_millis(20));
+        cache.prune(Duration::from_millis(10));
+        assert!(cache.get::<Vec<String>>(&key).is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_cache_redacted() {
+        let root =
+            std::env::temp_dir().join(format!("pii-masker-cache-redacted-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        let content = "email: \"jdoe@gmail.com\"";
+        let replacement = Replacement {
+            struct_name: "Student".to_string(),
+            field: "email".to_string(),
+            category: Category::Email,
+            inferred: false,
+            range: 8..22,
+            fake_range: 8..23,
+            line: 1,
+            column: 8,
+            original: "jdoe@gmail.com".to_string(),
+            fake: "joe@example.com".to_string(),
+        };
+        let cache = Cache::open(&root, &[]);
+        let key = cache.key(&[content]);
+        cache.put_redacted(&key, &vec![replacement]);
+
+        // the entry holds a digest of the original, recovered from the same content
+        let stored = fs::read_to_string(cache.path(&key)).unwrap();
+        assert!(!stored.contains("jdoe@gmail.com"));
+        assert!(stored.contains("joe@example.com"));
+        let cached: Vec<Replacement> = cache.get_redacted(&key, content, |_| true).unwrap();
+        assert_eq!(cached[0].original, "jdoe@gmail.com");
+
+        // other content at the range does not match the digest
+        let other = "email: \"anna@gmail.com\"";
+        assert!(cache
+            .get_redacted::<Vec<Replacement>>(&key, other, |_| true)
+            .is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
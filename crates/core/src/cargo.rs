@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use cargo_metadata::{MetadataCommand, Package};
+use pii_masker_pii::{MResult, MaskerError};
+
+use crate::workspace::Workspace;
+
+/// The name of the crate providing `#[derive(PIIMask)]`.
+pub const DERIVE_CRATE: &str = "pii-masker-derive";
+
+/// A package of the Cargo metadata and the directories holding its Rust files.
+#[derive(Debug, Clone)]
+pub struct CargoPackage {
+    pub name: String,
+    /// The directory of the package manifest.
+    pub root: PathBuf,
+    /// The `src/` and `tests/` directories of the package that exist.
+    pub dirs: Vec<PathBuf>,
+    /// Whether the package depends on `pii-masker-derive`.
+    pub uses_derive: bool,
+}
+
+/// The members of a Cargo workspace, found with `cargo metadata --offline`, and the
+/// dependencies outside the workspace that declare `#[pii_mask]` annotations.
+#[derive(Debug, Clone)]
+pub struct CargoWorkspace {
+    pub root: PathBuf,
+    pub members: Vec<CargoPackage>,
+    /// Dependencies using `pii-masker-derive`, whose annotations apply to the tests of
+    /// the members. They are never masked.
+    pub annotated_dependencies: Vec<CargoPackage>,
+}
+
+impl CargoWorkspace {
+    /// Runs `cargo metadata --offline` for the manifest at `manifest_path`, or the one
+    /// of the current directory, with the dependencies of the host platform. Nothing is
+    /// downloaded, so the dependencies must have been fetched already.
+    pub fn load(manifest_path: Option<&Path>) -> MResult<Self> {
+        let mut command = MetadataCommand::new();
+        if let Some(manifest_path) = manifest_path {
+            command.manifest_path(manifest_path);
+        }
+        let mut options = vec!["--offline".to_string()];
+        // dependencies of other platforms are usually not fetched
+        if let Some(host) = host_triple() {
+            options.extend(["--filter-platform".to_string(), host]);
+        }
+        command.other_options(options);
+        let metadata = command
+            .exec()
+            .map_err(|err| MaskerError::IoError(format!("cargo metadata: {}", err)))?;
+
+        let mut members = vec![];
+        let mut annotated_dependencies = vec![];
+        for package in &metadata.packages {
+            let package_info = cargo_package(package);
+            if metadata.workspace_members.contains(&package.id) {
+                members.push(package_info);
+            } else if package_info.uses_derive {
+                annotated_dependencies.push(package_info);
+            }
+        }
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+        annotated_dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(Self {
+            root: metadata.workspace_root.into_std_path_buf(),
+            members,
+            annotated_dependencies,
+        })
+    }
+
+    /// Builds the workspace masking the `src/` and `tests/` directories of every member
+    /// and the fixtures matching `fixture_globs` (relative to the workspace root), with
+    /// the annotations of the dependencies as well.
+    pub fn workspace(&self, fixture_globs: &[String]) -> MResult<Workspace> {
+        let includes: Vec<String> = self
+            .members
+            .iter()
+            .flat_map(|member| &member.dirs)
+            .map(|dir| {
+                let relative = dir.strip_prefix(&self.root).unwrap_or(dir);
+                format!("{}/**", relative.display())
+            })
+            .collect();
+        if includes.is_empty() {
+            return Ok(Workspace::from_paths(vec![], vec![]));
+        }
+        let workspace = Workspace::discover(
+            &self.root,
+            &includes,
+            &["target/**".to_string()],
+            fixture_globs,
+        )?;
+
+        let mut annotation_sources = vec![];
+        for dependency in &self.annotated_dependencies {
+            for dir in &dependency.dirs {
+                annotation_sources.extend(Workspace::discover(dir, &[], &[], &[])?.sources);
+            }
+        }
+        Ok(workspace.with_annotation_sources(annotation_sources))
+    }
+}
+
+/// Asks `rustc` for the target triple of the host.
+fn host_triple() -> Option<String> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc).arg("-vV").output().ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(str::to_string)
+}
+
+fn cargo_package(package: &Package) -> CargoPackage {
+    let root = package
+        .manifest_path
+        .parent()
+        .map(|dir| dir.as_std_path().to_path_buf())
+        .unwrap_or_default();
+    let dirs = ["src", "tests"]
+        .iter()
+        .map(|dir| root.join(dir))
+        .filter(|dir| dir.is_dir())
+        .collect();
+    CargoPackage {
+        name: package.name.clone(),
+        root,
+        dirs,
+        uses_derive: package
+            .dependencies
+            .iter()
+            .any(|dependency| dependency.name == DERIVE_CRATE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_cargo_workspace() {
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let cargo = CargoWorkspace::load(Some(&manifest)).unwrap();
+
+        let names: Vec<&str> = cargo
+            .members
+            .iter()
+            .map(|member| member.name.as_str())
+            .collect();
+        assert!(names.contains(&"pii-masker"));
+        let ri = cargo
+            .members
+            .iter()
+            .find(|member| member.name == "pii-masker-ri")
+            .unwrap();
+        assert!(ri.uses_derive);
+        assert!(cargo.annotated_dependencies.is_empty());
+
+        let workspace = cargo.workspace(&[]).unwrap();
+        assert!(workspace
+            .sources
+            .contains(&cargo.root.join("crates/ri/src/lib.rs")));
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use ast_grep_core::Language;
+use pii_masker_pii::similarity::{looks_synthetic, Category};
+use pii_masker_pii::MResult;
+use serde::{Deserialize, Serialize};
+
+use crate::masker::{Annotation, MaskContext, PIIMaskable};
+
+/// An annotated field in a test that still holds a value looking like real PII.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    pub path: PathBuf,
+    /// One-based line of the literal.
+    pub line: usize,
+    /// One-based byte column of the literal.
+    pub column: usize,
+    /// Byte range of the value in the source, quotes excluded.
+    pub range: Range<usize>,
+    pub struct_name: String,
+    pub field: String,
+    pub value: String,
+    pub category: Category,
+    /// Whether the category was inferred by the classifier rather than annotated.
+    pub inferred: bool,
+    /// A fake value the literal could be replaced with.
+    pub fake: Option<String>,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}.{} holds an unmasked {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.struct_name,
+            self.field,
+            self.category
+        )?;
+        if self.inferred {
+            write!(f, " (inferred)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports the annotated fields of the tests in `source` whose values look real.
+///
+/// Fields annotated as `inferred` are categorized with the classifier. Values of
+/// categories not selected by `context`, allowlisted values, the fake values earlier
+/// runs wrote to `path` and values that are recognizably synthetic are not reported.
+pub fn check_source<M: PIIMaskable<L>, L: Language>(
+    language: L,
+    path: &Path,
+    source: &str,
+    annotations: &Vec<Annotation>,
+    context: &MaskContext,
+) -> MResult<Vec<Finding>> {
+    let mut findings = vec![];
+    let grep = language.ast_grep(source);
+    let known_fakes = context.known_fakes.in_file(path);
+    for expectation in M::discover_expectations(language, &grep)? {
+        let Some(faker) =
+            M::lookup_faker_for_field(expectation.struct_name(), expectation.field(), annotations)
+        else {
+            continue;
+        };
+        if context.allowlist.contains(expectation.value())
+            || known_fakes.is_some_and(|fakes| fakes.contains(expectation.value()))
+        {
+            continue;
+        }
+
+        let (category, inferred) = context.resolve_category(faker, expectation.value())?;
+        if !context.masks_category(category) || looks_synthetic(expectation.value(), category) {
+            continue;
+        }
+
+        let (line, column) = expectation.start_pos();
+        findings.push(Finding {
+            path: path.to_path_buf(),
+            line: line + 1,
+            column: column + 1,
+            range: expectation.value_range(),
+            struct_name: expectation.struct_name().to_string(),
+            field: expectation.field().to_string(),
+            value: expectation.value().to_string(),
+            category,
+            inferred,
+            fake: context.fake_for(expectation.value(), category),
+        });
+    }
+    Ok(findings)
+}
+
+/// Reports the occurrences, in the lines of a fixture accepted by `in_scope`, of the
+/// values of `findings` in sources. Line numbers passed to `in_scope` are one-based.
+pub fn check_fixture(
+    path: &Path,
+    content: &str,
+    findings: &[Finding],
+    in_scope: impl Fn(usize) -> bool,
+) -> Vec<Finding> {
+    let mut fixture_findings = vec![];
+    let mut offset = 0;
+    for (index, line) in content.split_inclusive('\n').enumerate() {
+        if in_scope(index + 1) {
+            for finding in findings {
+                for (column, _) in line.match_indices(&finding.value) {
+                    fixture_findings.push(Finding {
+                        path: path.to_path_buf(),
+                        line: index + 1,
+                        column: column + 1,
+                        range: offset + column..offset + column + finding.value.len(),
+                        ..finding.clone()
+                    });
+                }
+            }
+        }
+        offset += line.len();
+    }
+    fixture_findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust::Rust;
+
+    #[test]
+    fn test_check_source() {
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="first_name")]
+    first_name: String,
+    #[pii_mask(faker="ssn")]
+    ssn: String,
+    #[pii_mask(faker="email")]
+    email: String,
+}
+
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        first_name: "John",
+        ssn: "000-12-3456",
+        email: "jdoe@gmail.com",
+    };
+}
+"#;
+        let annotations = Rust::discover_struct_annotations(&Rust::Rs, source).unwrap();
+        let word_pool = vec![];
+        let mut context = MaskContext::new(&word_pool);
+        context.allowlist.insert("jdoe@gmail.com".to_string());
+        let findings = check_source::<Rust, _>(
+            Rust::Rs,
+            Path::new("student.rs"),
+            source,
+            &annotations,
+            &context,
+        )
+        .unwrap();
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].to_string(),
+            "student.rs:15:21: Student.first_name holds an unmasked first_name"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use pii_masker_pii::similarity::{Category, FakeWordPool, Locale};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+use crate::config::Config;
+use crate::git;
+use crate::key::PseudonymKey;
+use crate::masker::MaskContext;
+use crate::workspace::Workspace;
+
+/// The settings of the fake values given on the command line of `pii-masker` or
+/// `cargo pii`, each replacing the configured one when set.
+#[derive(Debug, Default)]
+pub struct FakeSettings<'a> {
+    pub word_pool_size: Option<usize>,
+    pub seed: Option<u64>,
+    pub locale: Option<&'a str>,
+    pub key_file: Option<&'a Path>,
+    pub format_preserving: bool,
+    pub categories: &'a [String],
+}
+
+impl FakeSettings<'_> {
+    /// Generates the fake word pool, seeded when a seed is configured.
+    pub fn word_pool(&self, config: &Config) -> FakeWordPool {
+        let size = self
+            .word_pool_size
+            .or(config.masking.word_pool_size)
+            .unwrap_or(10000);
+        let locale = match self.locale {
+            Some(locale) => or_exit(Locale::from_str(locale), "parsing --locale"),
+            None => or_exit(config.locale(), "parsing locale").unwrap_or_default(),
+        };
+        // a keyed pool must be the same on every run for the fake values to be reproducible
+        let seed = self
+            .seed
+            .or(config.masking.seed)
+            .or_else(|| self.key(config).map(|key| key.word_pool_seed()));
+        let mut rng: Box<dyn RngCore> = match seed {
+            Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+            None => Box::new(rand::thread_rng()),
+        };
+        pii_masker_pii::similarity::generate_fake_words_pool_with_rng(size, locale, &mut rng)
+    }
+
+    /// The pseudonymization key of `--key-file`, or else of the environment or the
+    /// configuration.
+    pub fn key(&self, config: &Config) -> Option<PseudonymKey> {
+        let key = match self.key_file {
+            Some(path) => PseudonymKey::from_file(path).map(Some),
+            None => config.pseudonym_key(),
+        };
+        or_exit(key, "reading pseudonymization key")
+    }
+
+    /// Builds the context picking fake values from `word_pool` with the key, format and
+    /// categories of the settings, leaving the configured allowlist alone.
+    pub fn context<'a>(&self, config: &Config, word_pool: &'a FakeWordPool) -> MaskContext<'a> {
+        let mut context = MaskContext::new(word_pool);
+        context.key = self.key(config);
+        context.format_preserving = self.format_preserving || config.masking.format_preserving;
+        context.categories = if self.categories.is_empty() {
+            or_exit(config.categories(), "parsing categories")
+        } else {
+            self.categories
+                .iter()
+                .map(|category| or_exit(Category::from_str(category), "parsing --category"))
+                .collect()
+        };
+        context.allowlist = or_exit(config.allowlist(), "reading allowlist");
+        context
+    }
+}
+
+/// Leaves alone the fake values earlier runs wrote to the files under `root`.
+pub fn known_fakes(context: &mut MaskContext, config: &Config, root: &Path) {
+    context.known_fakes = or_exit(config.known_fakes(root), "reading fake values");
+}
+
+/// Exits unless none of `paths` has uncommitted git changes, so overwriting them can
+/// be undone.
+pub fn refuse_uncommitted(paths: &[PathBuf]) {
+    let dirty = or_exit(git::uncommitted(paths), "checking git status");
+    if !dirty.is_empty() {
+        eprintln!("Refusing to overwrite files with uncommitted changes (use --force):");
+        for path in dirty {
+            eprintln!("  {}", path.display());
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Reports the sources that could not be read or whose annotations could not be.
+pub fn print_errors(workspace: &Workspace) {
+    for err in workspace.take_errors() {
+        eprintln!("Error reading source: {:?}", err);
+    }
+}
+
+/// Unwraps `result`, or reports what failed and exits.
+pub fn or_exit<T, E: Debug>(result: Result<T, E>, what: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Error {}: {:?}", what, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_fake_settings() {
+        let config = Config::default();
+        let categories = ["email".to_string()];
+        let settings = FakeSettings {
+            word_pool_size: Some(50),
+            seed: Some(7),
+            categories: &categories,
+            ..FakeSettings::default()
+        };
+        let word_pool = settings.word_pool(&config);
+        // a seeded pool is the same on every run
+        assert_eq!(word_pool, settings.word_pool(&config));
+
+        let context = settings.context(&config, &word_pool);
+        assert_eq!(context.categories, HashSet::from([Category::Email]));
+        assert!(!context.format_preserving);
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use pii_masker_pii::similarity::{Category, Locale};
+use pii_masker_pii::{MResult, MaskerError};
+use serde::Deserialize;
+
+use crate::key::PseudonymKey;
+use crate::masker::KnownFakes;
+
+/// The name of the project configuration file.
+pub const CONFIG_FILE_NAME: &str = "pii-masker.toml";
+
+/// The name of the file listing the fake values written to each file by the masker.
+pub const FAKES_FILE_NAME: &str = "pii-masker.fakes";
+
+/// Project settings read from `pii-masker.toml`. Every section and key is optional;
+/// command line options take precedence over the values found here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub sources: SourcesConfig,
+    pub fixtures: FixturesConfig,
+    pub masking: MaskingConfig,
+    pub allowlist: AllowlistConfig,
+    pub output: OutputConfig,
+    /// The file the configuration was loaded from, `None` for the default one.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourcesConfig {
+    /// Only process sources matching one of these globs.
+    pub include: Vec<String>,
+    /// Skip files matching one of these globs, `target/**` when unset.
+    pub exclude: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FixturesConfig {
+    /// Mask the files matching one of these globs as fixtures.
+    pub globs: Vec<String>,
+    /// Also mask the fixtures referenced from the tests, e.g. with `include_str!`.
+    pub discover: bool,
+    pub mappings: Vec<FixtureMapping>,
+}
+
+/// Restricts the fixtures matching `fixtures` to the values masked in the sources
+/// matching `source`. Fixtures without a mapping receive the values of every source.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixtureMapping {
+    pub source: String,
+    pub fixtures: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaskingConfig {
+    pub word_pool_size: Option<usize>,
+    /// Seeds the fake word generator so every run picks the same fake values.
+    pub seed: Option<u64>,
+    /// The locale fake values are generated in, e.g. `fr_fr`.
+    pub locale: Option<String>,
+    /// Only mask values of these categories, every category when empty.
+    pub categories: Vec<String>,
+    /// A file holding the key fake values are derived from, relative to the
+    /// configuration file. `PII_MASKER_KEY` takes precedence.
+    pub key_file: Option<PathBuf>,
+    /// Shape the fake SSNs, emails, phone and card numbers, zip codes and decimals
+    /// like the original values.
+    pub format_preserving: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AllowlistConfig {
+    /// Values that are never masked nor reported.
+    pub values: Vec<String>,
+    /// Files listing such values, one per line.
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub in_place: bool,
+    pub backup_suffix: Option<String>,
+    /// Where `mask` saves the mapping used by `restore`.
+    pub mapping: Option<PathBuf>,
+    /// The encrypted vault `mask` records each run in, for `restore --vault`.
+    pub vault: Option<PathBuf>,
+    /// Where `mask` saves the JSON report of the replacements.
+    pub report: Option<PathBuf>,
+    /// Hash the original values in the report instead of listing them.
+    pub hash_originals: bool,
+    /// Where `mask` saves the HTML audit report.
+    pub html_report: Option<PathBuf>,
+    /// Number of leading characters of the original values shown in the HTML report.
+    pub redact_prefix: Option<usize>,
+}
+
+impl Config {
+    /// Looks for `pii-masker.toml` in `start` and its ancestors, and loads the nearest
+    /// one. Returns `None` when there is none.
+    pub fn find(start: &Path) -> MResult<Option<Self>> {
+        for dir in start.ancestors() {
+            let path = dir.join(CONFIG_FILE_NAME);
+            if path.is_file() {
+                return Self::load(&path).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn load(path: &Path) -> MResult<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+        let mut config: Config = toml::from_str(&content)
+            .map_err(|err| MaskerError::ConfigError(format!("{}: {}", path.display(), err)))?;
+        config.path = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// The directory holding the configuration file, which relative paths and globs
+    /// of the configuration are resolved against.
+    pub fn dir(&self) -> Option<&Path> {
+        self.path.as_deref().and_then(Path::parent)
+    }
+
+    /// Resolves a path of the configuration against the directory holding it.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        match self.dir() {
+            Some(dir) => dir.join(path),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// The pseudonymization key from `PII_MASKER_KEY`, or else from `key_file`.
+    pub fn pseudonym_key(&self) -> MResult<Option<PseudonymKey>> {
+        if let Some(key) = PseudonymKey::from_env()? {
+            return Ok(Some(key));
+        }
+        self.masking
+            .key_file
+            .as_ref()
+            .map(|path| PseudonymKey::from_file(&self.resolve(path)))
+            .transpose()
+    }
+
+    pub fn locale(&self) -> MResult<Option<Locale>> {
+        self.masking
+            .locale
+            .as_deref()
+            .map(Locale::from_str)
+            .transpose()
+            .map_err(|err| MaskerError::ConfigError(format!("{:?}", err)))
+    }
+
+    pub fn categories(&self) -> MResult<HashSet<Category>> {
+        self.masking
+            .categories
+            .iter()
+            .map(|category| {
+                Category::from_str(category)
+                    .map_err(|err| MaskerError::ConfigError(format!("{:?}", err)))
+            })
+            .collect()
+    }
+
+    /// Collects the allowlisted values, reading the allowlist files.
+    pub fn allowlist(&self) -> MResult<HashSet<String>> {
+        let mut allowlist: HashSet<String> = self.allowlist.values.iter().cloned().collect();
+        for path in &self.allowlist.files {
+            allowlist.extend(read_allowlist(&self.resolve(path))?);
+        }
+        Ok(allowlist)
+    }
+
+    /// Reads the fake values earlier runs wrote to the files of the workspace at
+    /// `root`, so they are neither masked again nor reported in those files.
+    pub fn known_fakes(&self, root: &Path) -> MResult<KnownFakes> {
+        let path = self.fakes_path(root);
+        if !path.is_file() {
+            return Ok(KnownFakes::default());
+        }
+        read_fakes(&path)
+    }
+
+    /// The file listing the fake values written to the files of the workspace at
+    /// `root`: next to the configuration file, or else at the root of the workspace.
+    pub fn fakes_path(&self, root: &Path) -> PathBuf {
+        match self.dir() {
+            Some(dir) => dir.join(FAKES_FILE_NAME),
+            None if root.is_file() => root.with_file_name(FAKES_FILE_NAME),
+            None => root.join(FAKES_FILE_NAME),
+        }
+    }
+}
+
+/// Reads an allowlist file, which lists one value per line. Blank lines are ignored.
+pub fn read_allowlist(path: &Path) -> MResult<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Reads a file of fake values, which lists one `path<TAB>value` per line with the
+/// paths relative to the directory holding it.
+pub fn read_fakes(path: &Path) -> MResult<KnownFakes> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut fakes = KnownFakes::default();
+    for (file, fake) in content.lines().filter_map(|line| line.split_once('\t')) {
+        fakes.insert(&dir.join(file), fake);
+    }
+    Ok(fakes)
+}
+
+/// Adds the `(file, fake)` values to the file of fake values at `path`, which is
+/// created if needed. Entries are kept sorted and unique, so the file diffs well when
+/// committed, and the file is left untouched when it already lists them all.
+pub fn record_fakes<'a>(
+    path: &Path,
+    fakes: impl IntoIterator<Item = (&'a Path, &'a str)>,
+) -> MResult<()> {
+    let mut entries = BTreeSet::new();
+    let mut known = String::new();
+    if path.is_file() {
+        known = fs::read_to_string(path)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+        entries.extend(
+            known
+                .lines()
+                .filter_map(|line| line.split_once('\t'))
+                .map(|(file, fake)| (file.to_string(), fake.to_string())),
+        );
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    for (file, fake) in fakes {
+        let file = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        let file = file.strip_prefix(&dir).unwrap_or(&file);
+        let file = file.to_string_lossy().replace('\\', "/");
+        if !fake.trim().is_empty() {
+            entries.insert((file, fake.to_string()));
+        }
+    }
+    let content: String = entries
+        .iter()
+        .map(|(file, fake)| format!("{}\t{}\n", file, fake))
+        .collect();
+    if content == known {
+        return Ok(());
+    }
+    fs::write(path, content)
+        .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_config() {
+        let root = std::env::temp_dir().join(format!("pii-masker-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("crates/core")).unwrap();
+        fs::write(root.join("allowed.txt"), "jdoe@example.com\n\n").unwrap();
+        fs::write(
+            root.join(CONFIG_FILE_NAME),
+            r#"
+[sources]
+include = ["tests/**"]
+
+[[fixtures.mappings]]
+source = "tests/student.rs"
+fixtures = ["fixtures/student*.yaml"]
+
+[masking]
+seed = 42
+locale = "fr-FR"
+categories = ["email", "ssn"]
+
+[allowlist]
+values = ["John"]
+files = ["allowed.txt"]
+"#,
+        )
+        .unwrap();
+
+        let config = Config::find(&root.join("crates/core")).unwrap().unwrap();
+        assert_eq!(config.dir(), Some(root.as_path()));
+        assert_eq!(config.sources.include, vec!["tests/**"]);
+        assert!(config.sources.exclude.is_none());
+        assert_eq!(config.fixtures.mappings[0].source, "tests/student.rs");
+        assert_eq!(config.masking.seed, Some(42));
+        assert_eq!(config.locale().unwrap(), Some(Locale::FrFr));
+        assert_eq!(
+            config.categories().unwrap(),
+            HashSet::from([Category::Email, Category::Ssn])
+        );
+        assert_eq!(
+            config.allowlist().unwrap(),
+            HashSet::from(["John".to_string(), "jdoe@example.com".to_string()])
+        );
+
+        fs::write(root.join(CONFIG_FILE_NAME), "[masking]\nsede = 42\n").unwrap();
+        assert!(matches!(
+            Config::find(&root),
+            Err(MaskerError::ConfigError(_))
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
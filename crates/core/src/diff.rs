@@ -0,0 +1,78 @@
+use similar::TextDiff;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders the change from `original` to `masked` as a git-style unified diff of the
+/// file at `path`, suitable for `git apply`. Returns an empty string when nothing
+/// changed.
+pub fn unified_diff(path: &str, original: &str, masked: &str) -> String {
+    if original == masked {
+        return String::new();
+    }
+    let diff = TextDiff::from_lines(original, masked);
+    let hunks = diff
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string();
+    format!("diff --git a/{} b/{}\n{}", path, path, hunks)
+}
+
+/// Adds ANSI colours to a unified diff for terminal output.
+pub fn colorize(diff: &str) -> String {
+    let mut colored = String::with_capacity(diff.len());
+    for line in diff.split_inclusive('\n') {
+        let color = if line.starts_with("diff --git")
+            || line.starts_with("--- ")
+            || line.starts_with("+++ ")
+        {
+            BOLD
+        } else if line.starts_with("@@") {
+            CYAN
+        } else if line.starts_with('-') {
+            RED
+        } else if line.starts_with('+') {
+            GREEN
+        } else {
+            colored.push_str(line);
+            continue;
+        };
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        colored.push_str(color);
+        colored.push_str(content);
+        colored.push_str(RESET);
+        colored.push_str(newline);
+    }
+    colored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let original = "student:\n  first_name: John\n  last_name: Doe\n";
+        let masked = "student:\n  first_name: Jane\n  last_name: Doe\n";
+        let diff = unified_diff("fixtures/student.yaml", original, masked);
+        assert_eq!(
+            diff,
+            "diff --git a/fixtures/student.yaml b/fixtures/student.yaml\n\
+             --- a/fixtures/student.yaml\n\
+             +++ b/fixtures/student.yaml\n\
+             @@ -1,3 +1,3 @@\n \
+             student:\n\
+             -  first_name: John\n\
+             +  first_name: Jane\n   \
+             last_name: Doe\n"
+        );
+        assert_eq!(unified_diff("a.yaml", original, original), "");
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::Read;
+
+use ast_grep_core::Language;
+use pii_masker_pii::{MResult, MaskerError};
+use serde::{Deserialize, Serialize};
+
+use crate::masker::{mask_fixture, MaskContext, PIIMaskable};
+
+/// A source and its fixtures exchanged as JSON over stdin and stdout, so editors and
+/// pipelines can mask content without going through files.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub source: String,
+    #[serde(default)]
+    pub fixtures: Vec<String>,
+}
+
+impl Envelope {
+    pub fn from_reader<R: Read>(reader: R) -> MResult<Self> {
+        serde_json::from_reader(reader)
+            .map_err(|err| MaskerError::IoError(format!("reading envelope: {}", err)))
+    }
+
+    pub fn to_json(&self) -> MResult<String> {
+        serde_json::to_string(self)
+            .map_err(|err| MaskerError::IoError(format!("writing envelope: {}", err)))
+    }
+
+    /// Masks the source with its own annotations and applies the replacements to every
+    /// fixture, returning the masked envelope.
+    pub fn mask<M: PIIMaskable<L>, L: Language>(
+        &self,
+        language: L,
+        context: &MaskContext,
+    ) -> MResult<Self> {
+        let annotations = M::discover_struct_annotations(&language, &self.source)?;
+        let (source, replacements) = M::mask_source(language, &self.source, &annotations, context)?;
+        let fixtures = self
+            .fixtures
+            .iter()
+            .map(|fixture| mask_fixture(fixture, &replacements).0)
+            .collect();
+        Ok(Self { source, fixtures })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust::Rust;
+
+    #[test]
+    fn test_mask_envelope() {
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="first_name")]
+    first_name: String,
+}
+
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        first_name: "Qwzyx",
+    };
+}
+"#;
+        let json = serde_json::json!({
+            "source": source,
+            "fixtures": ["first_name: Qwzyx\n", "{\"first_name\": \"Qwzyx\"}"],
+        })
+        .to_string();
+        let envelope = Envelope::from_reader(json.as_bytes()).unwrap();
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let masked = envelope
+            .mask::<Rust, _>(Rust::Rs, &MaskContext::new(&word_pool))
+            .unwrap();
+
+        assert!(!masked.source.contains("Qwzyx"));
+        assert_eq!(masked.fixtures.len(), 2);
+        assert!(masked
+            .fixtures
+            .iter()
+            .all(|fixture| !fixture.contains("Qwzyx")));
+        let round_trip = Envelope::from_reader(masked.to_json().unwrap().as_bytes()).unwrap();
+        assert_eq!(round_trip, masked);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use ast_grep_core::Language;
+use pii_masker_pii::{MResult, MaskerError};
+
+use crate::check::check_source;
+use crate::git;
+use crate::mapping::{is_token_at, FileKind, FileMapping};
+use crate::masker::{apply_replacements, MaskContext, PIIMaskable, Replacement};
+use crate::vault::EncryptedVault;
+use crate::workspace::Workspace;
+
+/// Where the vault is kept by default, relative to the root of the repository. The
+/// directory is excluded from git through `.git/info/exclude`, so the vault never leaves
+/// the machine.
+pub const VAULT_PATH: &str = ".pii-masker/vault.enc";
+
+/// The original values replaced by the clean filter in the files of a repository, used
+/// by the smudge filter to put them back in the working tree. They are kept in the
+/// encrypted vault `mask --vault` records its runs in.
+#[derive(Debug, Clone)]
+pub struct Vault {
+    path: PathBuf,
+    passphrase: String,
+    vault: EncryptedVault,
+}
+
+impl Vault {
+    /// Opens the vault at `path`, or else at [VAULT_PATH] in `repo`, which holds no
+    /// value until the clean filter first runs.
+    pub fn open(repo: &Path, path: Option<&Path>, passphrase: &str) -> MResult<Self> {
+        let path = path.map_or_else(|| repo.join(VAULT_PATH), Path::to_path_buf);
+        let vault = EncryptedVault::open(&path, passphrase)?;
+        Ok(Self {
+            path,
+            passphrase: passphrase.to_string(),
+            vault,
+        })
+    }
+
+    /// Writes the vault and, when it is kept in the repository, makes sure git ignores it.
+    pub fn save(&self, repo: &Path) -> MResult<()> {
+        if self.path == repo.join(VAULT_PATH) {
+            git::exclude(repo, "/.pii-masker/")?;
+        }
+        self.vault.save(&self.path, &self.passphrase)
+    }
+
+    /// Returns the fake value already chosen for `original` in any file.
+    fn fake_for(&self, original: &str) -> Option<&str> {
+        self.replacements()
+            .find(|replacement| replacement.original == original)
+            .map(|replacement| replacement.fake.as_str())
+    }
+
+    fn is_fake(&self, value: &str) -> bool {
+        self.replacements()
+            .any(|replacement| replacement.fake == value)
+    }
+
+    fn replacements(&self) -> impl Iterator<Item = &Replacement> {
+        self.vault
+            .filter
+            .files
+            .iter()
+            .flat_map(|file| &file.replacements)
+    }
+
+    /// The fake values stored for the file at `path`, relative to the repository.
+    pub fn fakes(&self, path: &Path) -> impl Iterator<Item = &str> {
+        self.file(path)
+            .into_iter()
+            .flat_map(|file| &file.replacements)
+            .map(|replacement| replacement.fake.as_str())
+    }
+
+    fn file(&self, path: &Path) -> Option<&FileMapping> {
+        self.vault
+            .filter
+            .files
+            .iter()
+            .find(|file| file.path == path)
+    }
+
+    /// Adds the replacements of values not recorded yet for `path`. Values replaced in
+    /// earlier versions of the file are kept, so older commits can still be smudged.
+    fn record(&mut self, path: &Path, kind: FileKind, replacements: Vec<Replacement>) {
+        let files = &mut self.vault.filter.files;
+        let index = match files.iter().position(|file| file.path == path) {
+            Some(index) => index,
+            None => {
+                files.push(FileMapping {
+                    path: path.to_path_buf(),
+                    kind,
+                    replacements: vec![],
+                });
+                files.len() - 1
+            }
+        };
+        let file = &mut files[index];
+        for replacement in replacements {
+            if !file
+                .replacements
+                .iter()
+                .any(|known| known.original == replacement.original)
+            {
+                file.replacements.push(replacement);
+            }
+        }
+    }
+}
+
+/// The git clean filter: masks the working tree `content` of the file at `path`
+/// (relative to `repo`) before it is stored, and records the replaced values in
+/// `vault`.
+///
+/// Rust files are masked like `check` would report them, with the annotations of the
+/// whole working tree. Only the files mentioning `pii_mask` are parsed for them, as
+/// found by `git grep`. Any other file is treated as a fixture, where the values already
+/// in the vault are replaced wherever they stand as whole tokens. A value keeps the fake
+/// it was first given, so cleaning the same content always stores the same blob.
+pub fn clean<M: PIIMaskable<L>, L: Language + Send + Sync>(
+    language: L,
+    repo: &Path,
+    path: &Path,
+    content: &str,
+    vault: &mut Vault,
+    context: &MaskContext,
+) -> MResult<String> {
+    if path.extension().is_some_and(|ext| ext == "rs") {
+        // the file itself is read from `content`, which may differ from the working tree
+        let declaring: Vec<PathBuf> = git::files_containing(repo, "pii_mask", "*.rs")?
+            .into_iter()
+            .filter(|declaring| *declaring != repo.join(path))
+            .collect();
+        let workspace = Workspace::from_paths(declaring, vec![]);
+        let mut annotations = M::discover_struct_annotations(&language, content)?;
+        for (_, file_annotations) in workspace.inventory::<M, L>(&language)? {
+            annotations.extend(file_annotations);
+        }
+        if let Some(err) = workspace.take_errors().into_iter().next() {
+            return Err(err);
+        }
+
+        let mut replacements = vec![];
+        let file = repo.join(path);
+        for finding in check_source::<M, L>(language, &file, content, &annotations, context)? {
+            // the working tree of a copy without the vault holds the stored fakes
+            if vault.is_fake(&finding.value) {
+                continue;
+            }
+            let Some(fake) = vault
+                .fake_for(&finding.value)
+                .map(str::to_string)
+                .or(finding.fake)
+            else {
+                return Err(MaskerError::SimilarityError(format!(
+                    "No fake {} available for {}.{}",
+                    finding.category, finding.struct_name, finding.field
+                )));
+            };
+            replacements.push(Replacement {
+                struct_name: finding.struct_name,
+                field: finding.field,
+                category: finding.category,
+                inferred: finding.inferred,
+                range: finding.range,
+                fake_range: 0..0,
+                line: finding.line,
+                column: finding.column,
+                original: finding.value,
+                fake,
+            });
+        }
+        let cleaned = apply_replacements(content, &mut replacements);
+        vault.record(path, FileKind::Source, replacements);
+        return Ok(cleaned);
+    }
+
+    let mut replacements = vec![];
+    let mut seen = HashSet::new();
+    for known in vault.replacements() {
+        if !seen.insert(&known.original) {
+            continue;
+        }
+        let tokens = content
+            .match_indices(&known.original)
+            .filter(|(start, _)| is_token_at(content, *start, &known.original));
+        for (start, _) in tokens {
+            let line = content[..start].matches('\n').count() + 1;
+            let column = start - content[..start].rfind('\n').map_or(0, |i| i + 1) + 1;
+            replacements.push(Replacement {
+                range: start..start + known.original.len(),
+                line,
+                column,
+                ..known.clone()
+            });
+        }
+    }
+    let cleaned = apply_replacements(content, &mut replacements);
+    vault.record(path, FileKind::Fixture, replacements);
+    Ok(cleaned)
+}
+
+/// The git smudge filter: puts the original values recorded in `vault` for the file at
+/// `path` back into its stored `content`, where their fake values stand as whole tokens.
+/// The content may be that of any commit, so the positions recorded for the latest
+/// one are not used. Files the vault knows nothing about are left as they are.
+pub fn smudge(path: &Path, content: &str, vault: &Vault) -> String {
+    match vault.file(path) {
+        Some(file) => file.restore_tokens(content),
+        None => content.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust::Rust;
+    use std::fs;
+
+    #[test]
+    fn test_clean_smudge() {
+        let repo = std::env::temp_dir().join(format!("pii-masker-filter-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&repo);
+        fs::create_dir_all(repo.join("src")).unwrap();
+        git::run(&repo, &["init", "-q"]).unwrap();
+        // the annotations are declared in another file of the working tree
+        fs::write(
+            repo.join("src/student.rs"),
+            r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="email")]
+    email: String,
+}
+"#,
+        )
+        .unwrap();
+        let source = r#"
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        email: "jdoe@gmail.com",
+    };
+}
+"#;
+        let fixture = "email: jdoe@gmail.com\ncc: ajdoe@gmail.com\n";
+
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let context = MaskContext::new(&word_pool);
+        let passphrase = "open sesame";
+        let mut vault = Vault::open(&repo, None, passphrase).unwrap();
+        let source_path = Path::new("tests/student.rs");
+        let fixture_path = Path::new("fixtures/student.yaml");
+        let cleaned =
+            clean::<Rust, _>(Rust::Rs, &repo, source_path, source, &mut vault, &context).unwrap();
+        let cleaned_fixture =
+            clean::<Rust, _>(Rust::Rs, &repo, fixture_path, fixture, &mut vault, &context).unwrap();
+        vault.save(&repo).unwrap();
+
+        assert!(!cleaned.contains("jdoe@gmail.com"));
+        let fake = vault.fake_for("jdoe@gmail.com").unwrap().to_string();
+        // a value inside a longer one is left alone
+        assert_eq!(
+            cleaned_fixture,
+            format!("email: {}\ncc: ajdoe@gmail.com\n", fake)
+        );
+
+        // the vault is encrypted, and cleaning again or cleaning the stored content
+        // changes nothing
+        let stored_vault = fs::read(repo.join(VAULT_PATH)).unwrap();
+        assert!(!String::from_utf8_lossy(&stored_vault).contains("jdoe"));
+        assert!(Vault::open(&repo, None, "open barley").is_err());
+        let mut vault = Vault::open(&repo, None, passphrase).unwrap();
+        let again =
+            clean::<Rust, _>(Rust::Rs, &repo, source_path, source, &mut vault, &context).unwrap();
+        assert_eq!(again, cleaned);
+        let stored =
+            clean::<Rust, _>(Rust::Rs, &repo, source_path, &cleaned, &mut vault, &context).unwrap();
+        assert_eq!(stored, cleaned);
+
+        assert_eq!(smudge(source_path, &cleaned, &vault), source);
+        assert_eq!(smudge(fixture_path, &cleaned_fixture, &vault), fixture);
+        // an older version of the source, with the value moved, is smudged too
+        let older = format!("// {}\n{}", fake, cleaned);
+        assert_eq!(
+            smudge(source_path, &older, &vault),
+            format!("// jdoe@gmail.com\n{}", source)
+        );
+        let status = git::run(&repo, &["status", "--porcelain", "--untracked-files=all"]).unwrap();
+        assert!(!status.contains(".pii-masker"));
+
+        fs::remove_dir_all(&repo).unwrap();
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use pii_masker_pii::{MResult, MaskerError};
+
+/// Runs `git` in `dir` and returns its standard output. Only the local `git`
+/// executable is used, nothing ever talks to a remote.
+pub(crate) fn run(dir: &Path, args: &[&str]) -> MResult<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|err| MaskerError::GitError(format!("failed to run git: {}", err)))?;
+    if !output.status.success() {
+        return Err(MaskerError::GitError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Returns the root of the git repository containing `dir`, if any.
+pub fn toplevel(dir: &Path) -> Option<PathBuf> {
+    run(dir, &["rev-parse", "--show-toplevel"])
+        .ok()
+        .map(|out| PathBuf::from(out.trim_end_matches('\n')))
+}
+
+/// Returns the subset of `paths` that have uncommitted changes (staged, unstaged or
+/// untracked) in their git repository. Files outside a repository are never reported.
+pub fn uncommitted(paths: &[PathBuf]) -> MResult<Vec<PathBuf>> {
+    let mut by_repo: BTreeMap<PathBuf, Vec<(&PathBuf, PathBuf)>> = BTreeMap::new();
+    for path in paths {
+        let absolute = fs::canonicalize(path)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+        let Some(repo) = absolute.parent().and_then(toplevel) else {
+            continue;
+        };
+        by_repo.entry(repo).or_default().push((path, absolute));
+    }
+
+    let mut dirty = vec![];
+    for (repo, files) in by_repo {
+        let changed = changed_files(&repo)?;
+        for (path, absolute) in files {
+            if changed.contains(&absolute) {
+                dirty.push(path.clone());
+            }
+        }
+    }
+    Ok(dirty)
+}
+
+/// A file staged in the index of a repository.
+#[derive(Debug, Clone)]
+pub struct StagedFile {
+    /// Path of the file in the working tree.
+    pub path: PathBuf,
+    /// Content of the file in the index.
+    pub content: String,
+    /// One-based line ranges, end excluded, added or modified by the staged changes.
+    pub changed_lines: Vec<Range<usize>>,
+}
+
+impl StagedFile {
+    pub fn is_changed(&self, line: usize) -> bool {
+        self.changed_lines.iter().any(|lines| lines.contains(&line))
+    }
+}
+
+/// Lists the files added, copied, modified or renamed in the index of `repo`, with
+/// their staged content and the lines their staged hunks touch.
+pub fn staged(repo: &Path) -> MResult<Vec<StagedFile>> {
+    let names = run(
+        repo,
+        &[
+            "diff",
+            "--cached",
+            "--name-only",
+            "-z",
+            "--diff-filter=ACMR",
+        ],
+    )?;
+    let mut staged = vec![];
+    for name in names.split('\0').filter(|name| !name.is_empty()) {
+        let content = run(repo, &["show", &format!(":{}", name)])?;
+        let diff = run(
+            repo,
+            &["diff", "--cached", "--unified=0", "--no-color", "--", name],
+        )?;
+        staged.push(StagedFile {
+            path: repo.join(name),
+            content,
+            changed_lines: added_lines(&diff),
+        });
+    }
+    Ok(staged)
+}
+
+/// Stages the current content of `paths` in the working tree.
+pub fn add(repo: &Path, paths: &[PathBuf]) -> MResult<()> {
+    let mut args = vec!["add", "--"];
+    let paths: Vec<String> = paths
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    args.extend(paths.iter().map(String::as_str));
+    run(repo, &args).map(|_| ())
+}
+
+/// Adds `pattern` to the local exclude file of `repo`, which unlike `.gitignore` is
+/// never committed.
+pub fn exclude(repo: &Path, pattern: &str) -> MResult<()> {
+    let path = run(repo, &["rev-parse", "--git-path", "info/exclude"])?;
+    let path = repo.join(path.trim_end_matches('\n'));
+    let content = fs::read_to_string(&path).unwrap_or_default();
+    if content.lines().any(|line| line == pattern) {
+        return Ok(());
+    }
+    let mut content = content;
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(pattern);
+    content.push('\n');
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", dir.display(), err)))?;
+    }
+    fs::write(&path, content)
+        .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))
+}
+
+/// Lists every commit reachable from a ref of `repo`, newest first.
+pub fn commits(repo: &Path) -> MResult<Vec<String>> {
+    let out = run(repo, &["rev-list", "--all"])?;
+    Ok(out.lines().map(str::to_string).collect())
+}
+
+/// Lists the files of `commit` as `(blob id, path)` pairs.
+pub fn tree(repo: &Path, commit: &str) -> MResult<Vec<(String, PathBuf)>> {
+    let out = run(repo, &["ls-tree", "-r", "-z", commit])?;
+    let mut files = vec![];
+    for entry in out.split('\0').filter(|entry| !entry.is_empty()) {
+        // <mode> SP <type> SP <object> TAB <path>
+        let Some((meta, path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let mut meta = meta.split(' ');
+        if let (Some(_), Some("blob"), Some(object)) = (meta.next(), meta.next(), meta.next()) {
+            files.push((object.to_string(), PathBuf::from(path)));
+        }
+    }
+    Ok(files)
+}
+
+/// Lists the files of the working tree of `repo` matching `pathspec` whose content holds
+/// `text`, tracked or not, leaving out the ignored ones.
+pub fn files_containing(repo: &Path, text: &str, pathspec: &str) -> MResult<Vec<PathBuf>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args([
+            "grep",
+            "--untracked",
+            "-l",
+            "-z",
+            "-F",
+            "-e",
+            text,
+            "--",
+            pathspec,
+        ])
+        .output()
+        .map_err(|err| MaskerError::GitError(format!("failed to run git: {}", err)))?;
+    // git grep exits with 1 when no file matches
+    match output.status.code() {
+        Some(0) | Some(1) => Ok(String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(|path| repo.join(path))
+            .collect()),
+        _ => Err(MaskerError::GitError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+/// Reads blobs of a repository through a single `git cat-file --batch` process, so
+/// reading many blobs does not spawn a process for each.
+pub struct BlobReader {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl BlobReader {
+    pub fn new(repo: &Path) -> MResult<Self> {
+        let failed =
+            |err: std::io::Error| MaskerError::GitError(format!("failed to run git: {}", err));
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(failed)?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = BufReader::new(child.stdout.take().expect("piped stdout"));
+        Ok(BlobReader {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    /// Reads the content of a blob.
+    pub fn read(&mut self, object: &str) -> MResult<String> {
+        let failed =
+            |err: std::io::Error| MaskerError::GitError(format!("reading {}: {}", object, err));
+        writeln!(self.stdin, "{}", object).map_err(failed)?;
+        self.stdin.flush().map_err(failed)?;
+
+        // <object> SP <type> SP <size> LF <content> LF, or <object> SP missing LF
+        let mut header = String::new();
+        self.stdout.read_line(&mut header).map_err(failed)?;
+        let mut fields = header.trim_end().split(' ');
+        let size = match (fields.next(), fields.next(), fields.next()) {
+            (Some(_), Some("blob"), Some(size)) => size.parse::<usize>().ok(),
+            _ => None,
+        };
+        let Some(size) = size else {
+            return Err(MaskerError::GitError(format!(
+                "{} is not a blob: {}",
+                object,
+                header.trim_end()
+            )));
+        };
+        let mut content = vec![0; size + 1];
+        self.stdout.read_exact(&mut content).map_err(failed)?;
+        content.truncate(size);
+        Ok(String::from_utf8_lossy(&content).to_string())
+    }
+}
+
+impl Drop for BlobReader {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Parses the `+start,count` side of the hunk headers of a unified diff.
+fn added_lines(diff: &str) -> Vec<Range<usize>> {
+    let mut lines = vec![];
+    for header in diff.lines().filter(|line| line.starts_with("@@ ")) {
+        let Some(added) = header.split(' ').find(|part| part.starts_with('+')) else {
+            continue;
+        };
+        let mut numbers = added[1..].split(',');
+        let start: usize = numbers.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+        let count: usize = numbers.next().and_then(|n| n.parse().ok()).unwrap_or(1);
+        if count > 0 {
+            lines.push(start..start + count);
+        }
+    }
+    lines
+}
+
+fn changed_files(repo: &Path) -> MResult<HashSet<PathBuf>> {
+    let status = run(
+        repo,
+        &["status", "--porcelain=v1", "-z", "--untracked-files=all"],
+    )?;
+    let repo = fs::canonicalize(repo).unwrap_or_else(|_| repo.to_path_buf());
+    let mut changed = HashSet::new();
+    let mut entries = status.split('\0');
+    while let Some(entry) = entries.next() {
+        if entry.len() < 4 {
+            continue;
+        }
+        let (code, path) = entry.split_at(3);
+        changed.insert(repo.join(path));
+        // renames and copies are followed by the original path
+        if code.starts_with('R') || code.starts_with('C') {
+            entries.next();
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uncommitted() {
+        let dir = std::env::temp_dir().join(format!("pii-masker-git-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        run(&dir, &["init", "-q"]).unwrap();
+        fs::write(dir.join("clean.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.join("dirty.rs"), "fn main() {}\n").unwrap();
+        run(&dir, &["add", "."]).unwrap();
+        run(
+            &dir,
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "-q",
+                "-m",
+                "init",
+            ],
+        )
+        .unwrap();
+        fs::write(dir.join("dirty.rs"), "fn main() { }\n").unwrap();
+        fs::write(dir.join("untracked.rs"), "fn main() {}\n").unwrap();
+
+        let paths = vec![
+            dir.join("clean.rs"),
+            dir.join("dirty.rs"),
+            dir.join("untracked.rs"),
+        ];
+        let dirty = uncommitted(&paths).unwrap();
+        assert_eq!(dirty, vec![dir.join("dirty.rs"), dir.join("untracked.rs")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_added_lines() {
+        let diff = "diff --git a/a.rs b/a.rs\n\
+                    @@ -3 +3 @@\n-old\n+new\n\
+                    @@ -10,0 +11,2 @@\n+one\n+two\n\
+                    @@ -20,2 +22,0 @@\n-gone\n-gone\n";
+        assert_eq!(added_lines(diff), vec![3..4, 11..13]);
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use ast_grep_core::Language;
+use globset::{Glob, GlobSetBuilder};
+use pii_masker_pii::similarity::Category;
+use pii_masker_pii::{MResult, MaskerError};
+
+use crate::check::{check_fixture, check_source, Finding};
+use crate::git;
+use crate::masker::{Annotation, MaskContext, PIIMaskable};
+
+/// A value looking like real PII found in a file of one or more past commits.
+#[derive(Debug, Clone)]
+pub struct Exposure {
+    /// Path of the file, relative to the repository.
+    pub path: PathBuf,
+    pub struct_name: String,
+    pub field: String,
+    pub value: String,
+    pub category: Category,
+    pub inferred: bool,
+    /// The commits holding the value in that file, newest first.
+    pub commits: Vec<String>,
+}
+
+/// A historical version of a source whose annotations or values could not be read.
+#[derive(Debug)]
+pub struct HistoryError {
+    /// The newest commit holding that version.
+    pub commit: String,
+    /// Path of the file, relative to the repository.
+    pub path: PathBuf,
+    pub error: MaskerError,
+}
+
+/// The exposures found by [history_scan], and the sources it could not check.
+#[derive(Debug, Default)]
+pub struct HistoryScan {
+    pub exposures: Vec<Exposure>,
+    pub errors: Vec<HistoryError>,
+}
+
+/// Walks every commit of `repo` and reports the values of annotated fields that look
+/// like real PII in any historical version of its Rust files, and their occurrences
+/// in the fixtures matching `fixture_globs`.
+///
+/// Every commit is checked with the annotations declared in that commit. Blobs are
+/// read through a single `git cat-file` process, and a source is only checked again
+/// when its annotations change. A version of a source that cannot be parsed, e.g.
+/// one using an outdated annotation syntax, is reported once and otherwise skipped.
+pub fn history_scan<M: PIIMaskable<L>, L: Language>(
+    language: L,
+    repo: &Path,
+    fixture_globs: &[String],
+    context: &MaskContext,
+) -> MResult<HistoryScan> {
+    let mut fixture_set = GlobSetBuilder::new();
+    for glob in fixture_globs {
+        fixture_set.add(Glob::new(glob).map_err(|err| MaskerError::GlobError(err.to_string()))?);
+    }
+    let fixture_set = fixture_set
+        .build()
+        .map_err(|err| MaskerError::GlobError(err.to_string()))?;
+
+    let mut blobs = git::BlobReader::new(repo)?;
+    let mut errors = vec![];
+    let mut blob_annotations: HashMap<String, Vec<Annotation>> = HashMap::new();
+    let mut blob_findings: HashMap<(String, String), Vec<Finding>> = HashMap::new();
+    let mut exposures: BTreeMap<(PathBuf, String, String, String), Exposure> = BTreeMap::new();
+
+    for commit in git::commits(repo)? {
+        let tree = git::tree(repo, &commit)?;
+        let is_source = |path: &Path| path.extension().is_some_and(|ext| ext == "rs");
+        let sources: Vec<&(String, PathBuf)> =
+            tree.iter().filter(|(_, path)| is_source(path)).collect();
+        let fixtures: Vec<&(String, PathBuf)> = tree
+            .iter()
+            .filter(|(_, path)| !is_source(path) && fixture_set.is_match(path))
+            .collect();
+
+        let mut annotations = vec![];
+        for (object, path) in &sources {
+            if !blob_annotations.contains_key(object) {
+                let content = blobs.read(object)?;
+                let found =
+                    M::discover_struct_annotations(&language, &content).unwrap_or_else(|error| {
+                        errors.push(HistoryError {
+                            commit: commit.clone(),
+                            path: path.clone(),
+                            error,
+                        });
+                        vec![]
+                    });
+                blob_annotations.insert(object.clone(), found);
+            }
+            annotations.extend(blob_annotations[object].iter().cloned());
+        }
+        let annotations_key: String = annotations
+            .iter()
+            .map(|annotation| {
+                format!(
+                    "{}.{}={};",
+                    annotation.struct_name(),
+                    annotation.field(),
+                    annotation.faker()
+                )
+            })
+            .collect();
+
+        let mut commit_findings = vec![];
+        for (object, path) in &sources {
+            let key = (object.clone(), annotations_key.clone());
+            if !blob_findings.contains_key(&key) {
+                let content = blobs.read(object)?;
+                let findings =
+                    check_source::<M, L>(language.clone(), path, &content, &annotations, context)
+                        .unwrap_or_else(|error| {
+                            errors.push(HistoryError {
+                                commit: commit.clone(),
+                                path: path.clone(),
+                                error,
+                            });
+                            vec![]
+                        });
+                blob_findings.insert(key.clone(), findings);
+            }
+            commit_findings.extend(blob_findings[&key].iter().cloned());
+        }
+
+        let mut seen = HashSet::new();
+        let values: Vec<Finding> = commit_findings
+            .iter()
+            .filter(|finding| seen.insert(finding.value.clone()))
+            .cloned()
+            .collect();
+        for (object, path) in &fixtures {
+            let content = blobs.read(object)?;
+            commit_findings.extend(check_fixture(path, &content, &values, |_| true));
+        }
+
+        for finding in commit_findings {
+            let key = (
+                finding.path.clone(),
+                finding.struct_name.clone(),
+                finding.field.clone(),
+                finding.value.clone(),
+            );
+            let exposure = exposures.entry(key).or_insert_with(|| Exposure {
+                path: finding.path.clone(),
+                struct_name: finding.struct_name.clone(),
+                field: finding.field.clone(),
+                value: finding.value.clone(),
+                category: finding.category,
+                inferred: finding.inferred,
+                commits: vec![],
+            });
+            if exposure.commits.last() != Some(&commit) {
+                exposure.commits.push(commit.clone());
+            }
+        }
+    }
+
+    Ok(HistoryScan {
+        exposures: exposures.into_values().collect(),
+        errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust::Rust;
+    use std::fs;
+
+    fn commit(repo: &Path, message: &str) -> String {
+        git::run(repo, &["add", "."]).unwrap();
+        git::run(
+            repo,
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "-q",
+                "-m",
+                message,
+            ],
+        )
+        .unwrap();
+        git::run(repo, &["rev-parse", "HEAD"])
+            .unwrap()
+            .trim()
+            .to_string()
+    }
+
+    #[test]
+    fn test_history_scan() {
+        let repo = std::env::temp_dir().join(format!("pii-masker-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&repo);
+        fs::create_dir_all(repo.join("fixtures")).unwrap();
+        git::run(&repo, &["init", "-q"]).unwrap();
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="ssn")]
+    ssn: String,
+}
+
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        ssn: "123-45-6789",
+    };
+}
+"#;
+        fs::write(repo.join("student.rs"), source).unwrap();
+        fs::write(repo.join("fixtures/student.yaml"), "ssn: 123-45-6789\n").unwrap();
+        let first = commit(&repo, "add student");
+
+        fs::write(
+            repo.join("student.rs"),
+            source.replace("123-45-6789", "000-45-6789"),
+        )
+        .unwrap();
+        fs::write(repo.join("fixtures/student.yaml"), "ssn: 000-45-6789\n").unwrap();
+        commit(&repo, "mask student");
+
+        // a version whose annotations cannot be parsed is reported, not skipped silently
+        fs::write(
+            repo.join("broken.rs"),
+            "#[derive(PIIMask)]\nstruct Broken {\n    #[pii_mask(first_name)]\n    first_name: String,\n}\n",
+        )
+        .unwrap();
+        let broken = commit(&repo, "add broken");
+
+        let word_pool = vec![];
+        let context = MaskContext::new(&word_pool);
+        let scan = history_scan::<Rust, _>(Rust::Rs, &repo, &["fixtures/**".to_string()], &context)
+            .unwrap();
+
+        assert_eq!(scan.errors.len(), 1);
+        assert_eq!(scan.errors[0].commit, broken);
+        assert_eq!(scan.errors[0].path, PathBuf::from("broken.rs"));
+        let exposures = scan.exposures;
+        assert_eq!(exposures.len(), 2);
+        assert_eq!(exposures[0].path, PathBuf::from("fixtures/student.yaml"));
+        assert_eq!(exposures[1].path, PathBuf::from("student.rs"));
+        for exposure in &exposures {
+            assert_eq!(exposure.value, "123-45-6789");
+            assert_eq!(exposure.category, Category::Ssn);
+            assert_eq!(exposure.commits, vec![first.clone()]);
+        }
+
+        fs::remove_dir_all(&repo).unwrap();
+    }
+}
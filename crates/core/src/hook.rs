@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ast_grep_core::Language;
+use globset::{Glob, GlobSetBuilder};
+use pii_masker_pii::{MResult, MaskerError};
+
+use crate::check::{check_fixture, check_source, Finding};
+use crate::git::{self, StagedFile};
+use crate::masker::{apply_replacements, MaskContext, PIIMaskable, Replacement};
+use crate::workspace::{MaskedFile, Workspace};
+
+/// The outcome of checking the staged changes of a repository before a commit.
+#[derive(Debug, Default)]
+pub struct PreCommit {
+    /// Unmasked values found in the changed lines of the staged sources and fixtures.
+    pub findings: Vec<Finding>,
+    /// The staged files with those values masked, when fixing was requested.
+    pub masked: Vec<MaskedFile>,
+    /// Files holding findings that could not be fixed because they also have unstaged
+    /// changes.
+    pub partially_staged: Vec<PathBuf>,
+}
+
+/// Checks the staged changes of `repo`: the literals of annotated fields in the
+/// changed lines of staged Rust files, and the values of the unmasked literals of those
+/// files found in the changed lines of staged fixtures matching `fixture_globs`. Only
+/// the staged content is checked; the annotations are read from the whole working tree,
+/// as structs may be declared in files that are not staged.
+///
+/// With `fix`, the findings are masked in the staged content of every file that has no
+/// unstaged changes. Nothing is written.
+pub fn pre_commit<M: PIIMaskable<L>, L: Language + Send + Sync>(
+    language: L,
+    repo: &Path,
+    fixture_globs: &[String],
+    context: &MaskContext,
+    fix: bool,
+) -> MResult<PreCommit> {
+    let staged = git::staged(repo)?;
+    let mut fixture_set = GlobSetBuilder::new();
+    for glob in fixture_globs {
+        fixture_set.add(Glob::new(glob).map_err(|err| MaskerError::GlobError(err.to_string()))?);
+    }
+    let fixture_set = fixture_set
+        .build()
+        .map_err(|err| MaskerError::GlobError(err.to_string()))?;
+    let (sources, fixtures): (Vec<&StagedFile>, Vec<&StagedFile>) = staged
+        .iter()
+        .filter(|file| {
+            let relative = file.path.strip_prefix(repo).unwrap_or(&file.path);
+            file.path.extension().is_some_and(|ext| ext == "rs") || fixture_set.is_match(relative)
+        })
+        .partition(|file| file.path.extension().is_some_and(|ext| ext == "rs"));
+
+    let workspace = Workspace::discover(repo, &[], &["target/**".to_string()], &[])?;
+    let mut annotations = vec![];
+    for (_, file_annotations) in workspace.inventory::<M, L>(&language)? {
+        annotations.extend(file_annotations);
+    }
+    for source in &sources {
+        annotations.extend(M::discover_struct_annotations(&language, &source.content)?);
+    }
+
+    let mut result = PreCommit::default();
+    // every unmasked value of the staged sources, to be looked for in the staged fixtures
+    let mut values = vec![];
+    for source in &sources {
+        let findings = check_source::<M, L>(
+            language.clone(),
+            &source.path,
+            &source.content,
+            &annotations,
+            context,
+        )?;
+        let in_hunks: Vec<Finding> = findings
+            .iter()
+            .filter(|finding| source.is_changed(finding.line))
+            .cloned()
+            .collect();
+        values.extend(findings);
+        if in_hunks.is_empty() {
+            continue;
+        }
+
+        if fix {
+            let (_, replacements) = M::mask_source_at(
+                language.clone(),
+                Some(&source.path),
+                &source.content,
+                &annotations,
+                context,
+            )?;
+            let replacements: Vec<Replacement> = replacements
+                .into_iter()
+                .filter(|replacement| {
+                    in_hunks
+                        .iter()
+                        .any(|finding| finding.range.start == replacement.range.start)
+                })
+                .collect();
+            result.fix(source, replacements);
+        }
+        result.findings.extend(in_hunks);
+    }
+
+    let mut seen = HashSet::new();
+    values.retain(|finding| seen.insert(finding.value.clone()));
+    for fixture in &fixtures {
+        let mut findings = check_fixture(&fixture.path, &fixture.content, &values, |line| {
+            fixture.is_changed(line)
+        });
+        for finding in &mut findings {
+            finding.fake = result.fake_for(finding).or(finding.fake.take());
+        }
+        if findings.is_empty() {
+            continue;
+        }
+
+        if fix {
+            let replacements = findings
+                .iter()
+                .filter_map(|finding| {
+                    let fake = finding.fake.clone()?;
+                    Some(Replacement {
+                        struct_name: finding.struct_name.clone(),
+                        field: finding.field.clone(),
+                        category: finding.category,
+                        inferred: finding.inferred,
+                        range: finding.range.clone(),
+                        fake_range: 0..0,
+                        line: finding.line,
+                        column: finding.column,
+                        original: finding.value.clone(),
+                        fake,
+                    })
+                })
+                .collect();
+            result.fix(fixture, replacements);
+        }
+        result.findings.extend(findings);
+    }
+
+    Ok(result)
+}
+
+impl PreCommit {
+    /// Returns the fake value already chosen for the value of `finding`, so a value is
+    /// replaced by the same fake in sources and fixtures.
+    fn fake_for(&self, finding: &Finding) -> Option<String> {
+        self.masked
+            .iter()
+            .flat_map(|file| &file.replacements)
+            .find(|replacement| replacement.original == finding.value)
+            .map(|replacement| replacement.fake.clone())
+    }
+
+    fn fix(&mut self, file: &StagedFile, mut replacements: Vec<Replacement>) {
+        if replacements.is_empty() {
+            return;
+        }
+        let working = fs::read_to_string(&file.path).unwrap_or_default();
+        if working != file.content {
+            self.partially_staged.push(file.path.clone());
+            return;
+        }
+        let masked = apply_replacements(&file.content, &mut replacements);
+        self.masked.push(MaskedFile {
+            path: file.path.clone(),
+            original: file.content.clone(),
+            masked,
+            replacements,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust::Rust;
+
+    #[test]
+    fn test_pre_commit() {
+        let repo = std::env::temp_dir().join(format!("pii-masker-hook-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&repo);
+        fs::create_dir_all(repo.join("fixtures")).unwrap();
+        git::run(&repo, &["init", "-q"]).unwrap();
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="email")]
+    email: String,
+}
+
+#[test]
+fn test_lookup_student() {
+    let first_student = Student {
+        email: "jdoe@gmail.com",
+    };
+}
+"#;
+        fs::write(repo.join("student.rs"), source).unwrap();
+        git::run(&repo, &["add", "."]).unwrap();
+        git::run(
+            &repo,
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "-q",
+                "-m",
+                "init",
+            ],
+        )
+        .unwrap();
+
+        let source = source.replace(
+            "    };\n}\n",
+            "    };\n    let second_student = Student {\n        email: \"mary.major@yahoo.com\",\n    };\n}\n",
+        );
+        fs::write(repo.join("student.rs"), &source).unwrap();
+        fs::write(
+            repo.join("fixtures/student.yaml"),
+            "email: mary.major@yahoo.com\nemail: asmith@yahoo.com\n",
+        )
+        .unwrap();
+        git::run(&repo, &["add", "."]).unwrap();
+        // neither staged nor checked, though its value is in a staged fixture
+        fs::write(
+            repo.join("other.rs"),
+            source.replace("mary.major@yahoo.com", "asmith@yahoo.com"),
+        )
+        .unwrap();
+
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let context = MaskContext::new(&word_pool);
+        let fixture_globs = ["fixtures/**".to_string()];
+        let result =
+            pre_commit::<Rust, _>(Rust::Rs, &repo, &fixture_globs, &context, true).unwrap();
+
+        // the committed email is outside the staged hunks
+        let values: Vec<(&str, usize)> = result
+            .findings
+            .iter()
+            .map(|finding| (finding.value.as_str(), finding.line))
+            .collect();
+        assert_eq!(
+            values,
+            vec![("mary.major@yahoo.com", 14), ("mary.major@yahoo.com", 1)]
+        );
+        assert_eq!(result.masked.len(), 2);
+        let masked_source = &result.masked[0].masked;
+        assert!(masked_source.contains("jdoe@gmail.com"));
+        assert!(!masked_source.contains("mary.major@yahoo.com"));
+        let fake = &result.masked[0].replacements[0].fake;
+        assert_eq!(
+            result.masked[1].masked,
+            format!("email: {}\nemail: asmith@yahoo.com\n", fake)
+        );
+
+        fs::remove_dir_all(&repo).unwrap();
+    }
+}
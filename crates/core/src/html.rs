@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::masker::{apply_edits, Annotation, Replacement};
+use crate::workspace::WorkspaceMasking;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #f0f0f0; }
+td.number { text-align: right; }
+pre { margin: 0; white-space: pre-wrap; }
+.before { background: #fdecea; }
+.after { background: #e8f5e9; }
+details { margin-bottom: 1em; }
+summary { cursor: pointer; font-weight: bold; }
+";
+
+/// Keeps the first `prefix` characters of `value` and hides the rest, e.g. `Jo**` for
+/// `John` with a prefix of 2.
+pub fn redact(value: &str, prefix: usize) -> String {
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i < prefix { c } else { '*' })
+        .collect()
+}
+
+/// Redacts the original value of every replacement in the `original` source, so no line
+/// shows an original value, even one masked by another replacement on the same line.
+fn redact_all(original: &str, replacements: &[Replacement], prefix: usize) -> String {
+    let edits = replacements
+        .iter()
+        .map(|replacement| {
+            let redacted = redact(&replacement.original, prefix);
+            (replacement.range.clone(), redacted)
+        })
+        .collect();
+    apply_edits(original, edits).unwrap_or_else(|_| {
+        // ranges not matching the source: redact every occurrence instead
+        replacements
+            .iter()
+            .fold(original.to_string(), |redacted, replacement| {
+                redacted.replace(
+                    &replacement.original,
+                    &redact(&replacement.original, prefix),
+                )
+            })
+    })
+}
+
+/// Renders a self-contained HTML page auditing a masking run: totals by category and
+/// by file, the annotated struct fields, and a before/after snippet of every masked
+/// literal. Original values are redacted to their first `redact_prefix` characters.
+pub fn html_report(
+    root: &Path,
+    inventory: &[(PathBuf, Vec<Annotation>)],
+    masking: &WorkspaceMasking,
+    redact_prefix: usize,
+) -> String {
+    let relative = |path: &Path| {
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+
+    let mut by_category: BTreeMap<String, usize> = BTreeMap::new();
+    for replacement in masking.sources.iter().flat_map(|file| &file.replacements) {
+        *by_category
+            .entry(replacement.category.to_string())
+            .or_default() += 1;
+    }
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>PII masking report</title>\n");
+    let _ = writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE);
+    let _ = writeln!(html, "<h1>PII masking report</h1>");
+    let _ = writeln!(
+        html,
+        "<p>Root: <code>{}</code></p>",
+        escape(&root.display().to_string())
+    );
+
+    html.push_str(
+        "<h2>Totals by category</h2>\n<table>\n<tr><th>Category</th><th>Literals</th></tr>\n",
+    );
+    for (category, count) in &by_category {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"number\">{}</td></tr>",
+            escape(category),
+            count
+        );
+    }
+    html.push_str("</table>\n");
+
+    html.push_str(
+        "<h2>Totals by file</h2>\n<table>\n<tr><th>File</th><th>Kind</th><th>Values</th></tr>\n",
+    );
+    for (kind, files) in [("source", &masking.sources), ("fixture", &masking.fixtures)] {
+        for file in files {
+            let _ = writeln!(
+                html,
+                "<tr><td><a href=\"#{}\">{}</a></td><td>{}</td><td class=\"number\">{}</td></tr>",
+                escape(&anchor(&relative(&file.path))),
+                escape(&relative(&file.path)),
+                kind,
+                file.replacements.len()
+            );
+        }
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Annotated fields</h2>\n<table>\n");
+    html.push_str("<tr><th>File</th><th>Struct</th><th>Field</th><th>Faker</th><th>Masked literals</th></tr>\n");
+    for (path, annotations) in inventory {
+        for annotation in annotations {
+            let masked = masking
+                .sources
+                .iter()
+                .flat_map(|file| &file.replacements)
+                .filter(|replacement| {
+                    replacement.struct_name == annotation.struct_name()
+                        && replacement.field == annotation.field()
+                })
+                .count();
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"number\">{}</td></tr>",
+                escape(&relative(path)),
+                escape(annotation.struct_name()),
+                escape(annotation.field()),
+                escape(annotation.faker()),
+                masked
+            );
+        }
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Masked literals</h2>\n");
+    for file in &masking.sources {
+        let path = relative(&file.path);
+        let _ = writeln!(
+            html,
+            "<details id=\"{}\" open>\n<summary>{} ({} literal(s))</summary>",
+            escape(&anchor(&path)),
+            escape(&path),
+            file.replacements.len()
+        );
+        html.push_str("<table>\n<tr><th>Line</th><th>Struct.field</th><th>Category</th><th>Before</th><th>After</th></tr>\n");
+        let redacted = redact_all(&file.original, &file.replacements, redact_prefix);
+        let original_lines: Vec<&str> = redacted.lines().collect();
+        let masked_lines: Vec<&str> = file.masked.lines().collect();
+        for replacement in &file.replacements {
+            let line = replacement.line.saturating_sub(1);
+            let before = original_lines.get(line).unwrap_or(&"");
+            let after = masked_lines.get(line).unwrap_or(&"");
+            let _ = writeln!(
+                html,
+                "<tr><td class=\"number\">{}</td><td>{}.{}</td><td>{}{}</td>\
+                 <td class=\"before\"><pre>{}</pre></td><td class=\"after\"><pre>{}</pre></td></tr>",
+                replacement.line,
+                escape(&replacement.struct_name),
+                escape(&replacement.field),
+                replacement.category,
+                if replacement.inferred { " (inferred)" } else { "" },
+                escape(before.trim()),
+                escape(after.trim())
+            );
+        }
+        html.push_str("</table>\n</details>\n");
+    }
+    for file in &masking.fixtures {
+        let path = relative(&file.path);
+        let _ = writeln!(
+            html,
+            "<details id=\"{}\">\n<summary>{} ({} value(s))</summary>\n<ul>",
+            escape(&anchor(&path)),
+            escape(&path),
+            file.replacements.len()
+        );
+        for replacement in &file.replacements {
+            let _ = writeln!(
+                html,
+                "<li><code>{}</code> &rarr; <code>{}</code></li>",
+                escape(&redact(&replacement.original, redact_prefix)),
+                escape(&replacement.fake)
+            );
+        }
+        html.push_str("</ul>\n</details>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn anchor(path: &str) -> String {
+    format!(
+        "file-{}",
+        path.replace(|c: char| !c.is_ascii_alphanumeric(), "-")
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workspace::MaskedFile;
+    use pii_masker_pii::similarity::Category;
+
+    #[test]
+    fn test_html_report() {
+        let replacement =
+            |field: &str, range, fake_range, original: &str, fake: &str| Replacement {
+                struct_name: "Student".to_string(),
+                field: field.to_string(),
+                category: Category::FirstName,
+                inferred: false,
+                range,
+                fake_range,
+                line: 2,
+                column: 17,
+                original: original.to_string(),
+                fake: fake.to_string(),
+            };
+        let masking = WorkspaceMasking {
+            sources: vec![MaskedFile {
+                path: PathBuf::from("/ws/tests/student.rs"),
+                original: "let s = Student {\n    first_name: \"John\", nickname: \"Johnny\" };\n"
+                    .to_string(),
+                masked: "let s = Student {\n    first_name: \"Jane\", nickname: \"Bob\" };\n"
+                    .to_string(),
+                replacements: vec![
+                    replacement("first_name", 35..39, 35..39, "John", "Jane"),
+                    replacement("nickname", 53..59, 53..56, "Johnny", "Bob"),
+                ],
+            }],
+            fixtures: vec![],
+        };
+        let inventory = vec![(
+            PathBuf::from("/ws/src/student.rs"),
+            vec![Annotation::new(
+                "Student".to_string(),
+                "first_name".to_string(),
+                "first_name".to_string(),
+                None,
+            )],
+        )];
+
+        let html = html_report(Path::new("/ws"), &inventory, &masking, 1);
+        assert_eq!(redact("John", 1), "J***");
+        assert!(html.contains("<td>first_name</td><td class=\"number\">1</td>"));
+        assert!(html.contains("first_name: &quot;J***&quot;, nickname: &quot;J*****&quot;"));
+        assert!(html.contains("first_name: &quot;Jane&quot;"));
+        assert!(!html.contains("John"));
+    }
+}
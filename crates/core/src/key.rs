@@ -0,0 +1,188 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use hmac::{Hmac, Mac};
+use pii_masker_pii::similarity::Category;
+use pii_masker_pii::{MResult, MaskerError};
+use sha2::Sha256;
+
+/// The environment variable holding the pseudonymization key itself.
+pub const KEY_ENV: &str = "PII_MASKER_KEY";
+
+/// Keys shorter than this are refused, as they could be guessed.
+const MIN_KEY_LEN: usize = 16;
+
+/// A secret key making the fake value of an original one a deterministic function of
+/// the key, the category and the original value: HMAC-SHA256 of the category and the
+/// value picks the fake value among the values of the category in the pool, and the
+/// pool itself is seeded from the key.
+///
+/// Masking the same repository twice with the same key gives the same output, and
+/// the originals cannot be told from the fakes without the key. Only values whose
+/// fakes would collide depend on which of them is masked first.
+#[derive(Clone)]
+pub struct PseudonymKey(Vec<u8>);
+
+impl fmt::Debug for PseudonymKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PseudonymKey(..)")
+    }
+}
+
+impl PseudonymKey {
+    pub fn new(key: &[u8]) -> MResult<Self> {
+        if key.len() < MIN_KEY_LEN {
+            return Err(MaskerError::ConfigError(format!(
+                "the pseudonymization key must be at least {} bytes long",
+                MIN_KEY_LEN
+            )));
+        }
+        Ok(Self(key.to_vec()))
+    }
+
+    /// Reads the key from a file, ignoring trailing whitespace such as a final newline.
+    pub fn from_file(path: &Path) -> MResult<Self> {
+        let content = fs::read(path)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+        let len = content
+            .iter()
+            .rposition(|byte| !byte.is_ascii_whitespace())
+            .map_or(0, |last| last + 1);
+        Self::new(&content[..len])
+    }
+
+    /// Reads the key from `PII_MASKER_KEY`, `None` when it is not set.
+    pub fn from_env() -> MResult<Option<Self>> {
+        match std::env::var(KEY_ENV) {
+            Ok(key) => Self::new(key.trim_end().as_bytes()).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The seed the fake word pool is generated from, so the pool is the same on every
+    /// run with this key.
+    pub fn word_pool_seed(&self) -> u64 {
+        let mac = self.mac(&["word-pool"]);
+        u64::from_le_bytes(mac[..8].try_into().unwrap_or_default())
+    }
+
+    /// A digest identifying the key without revealing it, e.g. for cache keys.
+    pub fn fingerprint(&self) -> String {
+        hex::encode(self.mac(&["fingerprint"]))
+    }
+
+    /// Picks the fake value of `original` among `words`, the values of `category` in
+    /// the pool, never `original` itself. `None` when there is nothing to pick from.
+    ///
+    /// The word at the index the key derives from `original` is taken unless `taken`
+    /// tells it is already the fake value of another value, in which case the next
+    /// index is tried. Only when every word is taken is one shared: the first one that
+    /// is not `original`.
+    pub fn pick<'w>(
+        &self,
+        category: Category,
+        original: &str,
+        words: &'w [String],
+        taken: impl Fn(&str) -> bool,
+    ) -> Option<&'w String> {
+        let mac = self.mac(&["pseudonym", &category.to_string(), original]);
+        let start = u64::from_le_bytes(mac[..8].try_into().unwrap_or_default());
+        let start = (start % words.len().max(1) as u64) as usize;
+        let mut candidates = (0..words.len())
+            .map(|offset| &words[(start + offset) % words.len()])
+            .filter(|word| *word != original);
+        let first = candidates.clone().next();
+        candidates.find(|word| !taken(word)).or(first)
+    }
+
+    /// The seed of the random generator shaping the fake value of `original`, for
+    /// format-preserving masking.
+    pub fn rng_seed(&self, category: Category, original: &str) -> [u8; 32] {
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&self.mac(&["format", &category.to_string(), original]));
+        seed
+    }
+
+    /// HMAC-SHA256 of the parts, each prefixed with its length so their boundaries count.
+    fn mac(&self, parts: &[&str]) -> Vec<u8> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        for part in parts {
+            mac.update(&(part.len() as u64).to_le_bytes());
+            mac.update(part.as_bytes());
+        }
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::masker::MaskContext;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_keyed_pseudonyms() {
+        let key = PseudonymKey::new(b"correct horse battery staple").unwrap();
+        let fake = |key: &PseudonymKey| {
+            let mut rng = StdRng::seed_from_u64(key.word_pool_seed());
+            let word_pool = pii_masker_pii::similarity::generate_fake_words_pool_with_rng(
+                100,
+                Default::default(),
+                &mut rng,
+            );
+            let mut context = MaskContext::new(&word_pool);
+            context.key = Some(key.clone());
+            (
+                context.fake_for("jdoe@gmail.com", Category::Email).unwrap(),
+                context.fake_for("Doe", Category::LastName).unwrap(),
+            )
+        };
+
+        // the same key gives the same fakes on every run
+        assert_eq!(fake(&key), fake(&key));
+        let other = PseudonymKey::new(b"another secret of some length").unwrap();
+        assert_ne!(fake(&key), fake(&other));
+        assert_ne!(key.fingerprint(), other.fingerprint());
+
+        // a value whose pool entry is already taken falls back to the next one
+        let words: Vec<String> = ["Ann", "Bob", "Cid"].map(String::from).to_vec();
+        let first = key
+            .pick(Category::FirstName, "Zoë", &words, |_| false)
+            .unwrap();
+        let next = key
+            .pick(Category::FirstName, "Zoë", &words, |word| word == first)
+            .unwrap();
+        assert_ne!(next, first);
+        let index = |word| words.iter().position(|other| other == word).unwrap();
+        assert_eq!(index(next), (index(first) + 1) % words.len());
+
+        // in a run, two values never get the same fake even from a tiny pool
+        let word_pool = vec![(Category::FirstName, words.clone())];
+        let mut context = MaskContext::new(&word_pool);
+        context.key = Some(key.clone());
+        let fakes: BTreeSet<String> = ["John", "Jane", "Zoë"]
+            .iter()
+            .map(|name| context.fake_for(name, Category::FirstName).unwrap())
+            .collect();
+        assert_eq!(fakes.len(), 3);
+
+        // values given their fakes up front get the same ones whatever their order
+        let assigned = |names: [&str; 3]| {
+            let mut context = MaskContext::new(&word_pool);
+            context.key = Some(key.clone());
+            let values = names.map(|name| (Category::FirstName, name.to_string()));
+            context.assign_fakes(values.to_vec());
+            names.map(|name| context.pseudonyms.get(Category::FirstName, name).unwrap())
+        };
+        let mut reversed = assigned(["Zoë", "Jane", "John"]);
+        reversed.reverse();
+        assert_eq!(assigned(["John", "Jane", "Zoë"]), reversed);
+
+        assert!(PseudonymKey::new(b"short").is_err());
+        assert_eq!(format!("{:?}", key), "PseudonymKey(..)");
+    }
+}
@@ -1,5 +1,47 @@
+pub mod cache;
+
+pub mod cargo;
+
+pub mod check;
+
+pub mod cli;
+
+pub mod config;
+
+pub mod diff;
+
+pub mod envelope;
+
+pub mod filter;
+
+pub mod git;
+
+pub mod history;
+
+pub mod hook;
+
+pub mod html;
+
+pub mod key;
+
+pub mod lsp;
+
+pub mod mapping;
+
 pub mod masker;
 
+pub mod output;
+
+pub mod report;
+
 pub mod rust;
 
+pub mod sarif;
+
+pub mod server;
+
+pub mod vault;
+
+pub mod watch;
 
+pub mod workspace;
@@ -0,0 +1,574 @@
+use std::collections::{BTreeSet, HashMap};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+use ast_grep_core::Language;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{CodeActionRequest, HoverRequest, Request as _};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams,
+    CodeActionProviderCapability, Diagnostic, DiagnosticSeverity, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, MarkupContent, MarkupKind, NumberOrString, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+};
+use pii_masker_pii::{MResult, MaskerError};
+
+use crate::check::{check_source, Finding};
+use crate::config;
+use crate::masker::{Annotation, MaskContext, PIIMaskable};
+use crate::sarif::rule_id;
+use crate::workspace::Workspace;
+
+/// The state of a language server session: the open documents and the annotations
+/// declared in the workspace, so structs declared in one file are checked in the tests
+/// of another.
+pub struct Session<'a, M, L> {
+    language: L,
+    context: MaskContext<'a>,
+    workspace: Workspace,
+    annotations: Vec<Annotation>,
+    documents: HashMap<Url, String>,
+    /// The fake values offered by quick fixes in each document, recorded in
+    /// `fakes_path` once the document is saved with them.
+    offered: HashMap<Url, BTreeSet<String>>,
+    fakes_path: Option<PathBuf>,
+    maskable: PhantomData<M>,
+}
+
+impl<'a, M: PIIMaskable<L>, L: Language + Send + Sync> Session<'a, M, L> {
+    pub fn new(language: L, context: MaskContext<'a>, workspace: Workspace) -> MResult<Self> {
+        let mut session = Self {
+            language,
+            context,
+            workspace,
+            annotations: vec![],
+            documents: HashMap::new(),
+            offered: HashMap::new(),
+            fakes_path: None,
+            maskable: PhantomData,
+        };
+        session.refresh_annotations()?;
+        Ok(session)
+    }
+
+    /// Records the fake values the quick fixes write in the file of fake values at
+    /// `path`, so `check` accepts them.
+    pub fn with_fakes_path(mut self, path: PathBuf) -> Self {
+        self.fakes_path = Some(path);
+        self
+    }
+
+    /// Reads the annotations of the workspace sources again, e.g. after a file is saved.
+    fn refresh_annotations(&mut self) -> MResult<()> {
+        self.annotations = self
+            .workspace
+            .inventory::<M, L>(&self.language)?
+            .into_iter()
+            .flat_map(|(_, annotations)| annotations)
+            .collect();
+        Ok(())
+    }
+
+    /// The annotations of the workspace and those declared in the unsaved `content`.
+    fn annotations_for(&self, content: &str) -> Vec<Annotation> {
+        let mut annotations = self.annotations.clone();
+        // the document may be mid-edit and not parse
+        annotations
+            .extend(M::discover_struct_annotations(&self.language, content).unwrap_or_default());
+        annotations
+    }
+
+    fn findings(&self, uri: &Url) -> Vec<Finding> {
+        let Some(content) = self.documents.get(uri) else {
+            return vec![];
+        };
+        let annotations = self.annotations_for(content);
+        let path = uri.to_file_path().unwrap_or_default();
+        check_source::<M, L>(
+            self.language.clone(),
+            &path,
+            content,
+            &annotations,
+            &self.context,
+        )
+        .unwrap_or_default()
+    }
+
+    /// Reports a warning on every annotated literal of the document still holding a
+    /// value that looks real. The diagnostic code is the SARIF rule id of the finding.
+    pub fn diagnostics(&self, uri: &Url) -> Vec<Diagnostic> {
+        let Some(content) = self.documents.get(uri) else {
+            return vec![];
+        };
+        self.findings(uri)
+            .iter()
+            .map(|finding| Diagnostic {
+                range: range(content, &finding.range),
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String(rule_id(finding))),
+                source: Some("pii-masker".to_string()),
+                message: format!(
+                    "{}.{} holds an unmasked {}{}",
+                    finding.struct_name,
+                    finding.field,
+                    finding.category,
+                    if finding.inferred { " (inferred)" } else { "" }
+                ),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Offers to replace every unmasked literal overlapping `selection` with a fake value.
+    /// The fake values offered are known fakes of the document from then on, so the
+    /// diagnostic clears once one is inserted.
+    pub fn code_actions(&mut self, uri: &Url, selection: Range) -> Vec<CodeActionOrCommand> {
+        let Some(content) = self.documents.get(uri) else {
+            return vec![];
+        };
+        let selected = offset(content, selection.start)..offset(content, selection.end);
+        let diagnostics = self.diagnostics(uri);
+        let actions: Vec<(String, CodeAction)> = self
+            .findings(uri)
+            .into_iter()
+            .zip(diagnostics)
+            .filter(|(finding, _)| {
+                finding.range.start <= selected.end && selected.start <= finding.range.end
+            })
+            .filter_map(|(finding, diagnostic)| {
+                let fake = finding.fake?;
+                // a string literal keeps its quotes, other literals become strings
+                let new_text = if content[..finding.range.start].ends_with('"') {
+                    fake.clone()
+                } else {
+                    format!("\"{}\"", fake)
+                };
+                let edit = TextEdit {
+                    range: diagnostic.range,
+                    new_text,
+                };
+                let action = CodeAction {
+                    title: format!("Replace with fake {} \"{}\"", finding.category, fake),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                        ..Default::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..Default::default()
+                };
+                Some((fake, action))
+            })
+            .collect();
+
+        let path = uri.to_file_path().unwrap_or_default();
+        let fakes = actions.iter().map(|(fake, _)| fake.as_str());
+        self.context.known_fakes.extend(&path, fakes);
+        let offered = self.offered.entry(uri.clone()).or_default();
+        offered.extend(actions.iter().map(|(fake, _)| fake.clone()));
+        actions
+            .into_iter()
+            .map(|(_, action)| CodeActionOrCommand::CodeAction(action))
+            .collect()
+    }
+
+    /// Records the fake values offered in the document at `uri` that it holds.
+    fn record_offered(&mut self, uri: &Url) -> MResult<()> {
+        let (Some(fakes_path), Some(content), Ok(path)) = (
+            &self.fakes_path,
+            self.documents.get(uri),
+            uri.to_file_path(),
+        ) else {
+            return Ok(());
+        };
+        let offered = self.offered.remove(uri).unwrap_or_default();
+        let written = offered
+            .iter()
+            .filter(|fake| content.contains(fake.as_str()))
+            .map(|fake| (path.as_path(), fake.as_str()));
+        config::record_fakes(fakes_path, written)
+    }
+
+    /// Names the category of the annotated literal under `position`, and whether the
+    /// classifier inferred it.
+    pub fn hover(&self, uri: &Url, position: Position) -> Option<Hover> {
+        let content = self.documents.get(uri)?;
+        let at = offset(content, position);
+        let annotations = self.annotations_for(content);
+        let grep = self.language.ast_grep(content);
+        let expectations = M::discover_expectations(self.language.clone(), &grep).ok()?;
+        let expectation = expectations
+            .iter()
+            .find(|expectation| expectation.range().contains(&at))?;
+        let faker = M::lookup_faker_for_field(
+            expectation.struct_name(),
+            expectation.field(),
+            &annotations,
+        )?;
+        let (category, inferred) = self
+            .context
+            .resolve_category(faker, expectation.value())
+            .ok()?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!(
+                    "`{}.{}`: PII category **{}**{}",
+                    expectation.struct_name(),
+                    expectation.field(),
+                    category,
+                    if inferred { " (inferred)" } else { "" }
+                ),
+            }),
+            range: Some(range(content, &expectation.range())),
+        })
+    }
+
+    pub fn handle_request(&mut self, request: Request) -> Response {
+        match request.method.as_str() {
+            CodeActionRequest::METHOD => {
+                match serde_json::from_value::<CodeActionParams>(request.params) {
+                    Ok(params) => Response::new_ok(
+                        request.id,
+                        self.code_actions(&params.text_document.uri, params.range),
+                    ),
+                    Err(err) => invalid_params(request.id, err),
+                }
+            }
+            HoverRequest::METHOD => match serde_json::from_value::<HoverParams>(request.params) {
+                Ok(params) => {
+                    let document = params.text_document_position_params;
+                    Response::new_ok(
+                        request.id,
+                        self.hover(&document.text_document.uri, document.position),
+                    )
+                }
+                Err(err) => invalid_params(request.id, err),
+            },
+            method => Response::new_err(
+                request.id,
+                lsp_server::ErrorCode::MethodNotFound as i32,
+                format!("unsupported request {}", method),
+            ),
+        }
+    }
+
+    /// Updates the documents and returns the diagnostics to publish in response.
+    pub fn handle_notification(&mut self, notification: Notification) -> Vec<Notification> {
+        let params = notification.params;
+        let uris = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Ok(params) =
+                    serde_json::from_value::<lsp_types::DidOpenTextDocumentParams>(params)
+                else {
+                    return vec![];
+                };
+                let uri = params.text_document.uri;
+                self.documents
+                    .insert(uri.clone(), params.text_document.text);
+                vec![uri]
+            }
+            DidChangeTextDocument::METHOD => {
+                let Ok(params) =
+                    serde_json::from_value::<lsp_types::DidChangeTextDocumentParams>(params)
+                else {
+                    return vec![];
+                };
+                // the server asks for full document sync
+                let uri = params.text_document.uri;
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(uri.clone(), change.text);
+                }
+                vec![uri]
+            }
+            DidSaveTextDocument::METHOD => {
+                if let Ok(params) =
+                    serde_json::from_value::<lsp_types::DidSaveTextDocumentParams>(params)
+                {
+                    if let Err(err) = self.record_offered(&params.text_document.uri) {
+                        eprintln!("Error recording fake values: {:?}", err);
+                    }
+                }
+                if let Err(err) = self.refresh_annotations() {
+                    eprintln!("Error reading annotations: {:?}", err);
+                }
+                self.documents.keys().cloned().collect()
+            }
+            DidCloseTextDocument::METHOD => {
+                let Ok(params) =
+                    serde_json::from_value::<lsp_types::DidCloseTextDocumentParams>(params)
+                else {
+                    return vec![];
+                };
+                self.documents.remove(&params.text_document.uri);
+                // clear the diagnostics of the closed document
+                return vec![publish(params.text_document.uri, vec![])];
+            }
+            _ => vec![],
+        };
+        uris.into_iter()
+            .map(|uri| {
+                let diagnostics = self.diagnostics(&uri);
+                publish(uri, diagnostics)
+            })
+            .collect()
+    }
+}
+
+/// Speaks LSP over stdin and stdout until the client shuts the server down.
+pub fn run<M: PIIMaskable<L>, L: Language + Send + Sync>(
+    mut session: Session<M, L>,
+) -> MResult<()> {
+    let protocol_error = |err: lsp_server::ProtocolError| MaskerError::IoError(err.to_string());
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        ..Default::default()
+    };
+    let capabilities =
+        serde_json::to_value(capabilities).map_err(|err| MaskerError::IoError(err.to_string()))?;
+    connection
+        .initialize(capabilities)
+        .map_err(protocol_error)?;
+
+    for message in &connection.receiver {
+        let replies = match message {
+            Message::Request(request) => {
+                if connection
+                    .handle_shutdown(&request)
+                    .map_err(protocol_error)?
+                {
+                    break;
+                }
+                vec![Message::Response(session.handle_request(request))]
+            }
+            Message::Notification(notification) => session
+                .handle_notification(notification)
+                .into_iter()
+                .map(Message::Notification)
+                .collect(),
+            Message::Response(_) => vec![],
+        };
+        for reply in replies {
+            connection
+                .sender
+                .send(reply)
+                .map_err(|err| MaskerError::IoError(err.to_string()))?;
+        }
+    }
+    // the writer thread only stops once the connection is gone
+    drop(connection);
+    io_threads
+        .join()
+        .map_err(|err| MaskerError::IoError(err.to_string()))
+}
+
+fn publish(uri: Url, diagnostics: Vec<Diagnostic>) -> Notification {
+    Notification::new(
+        PublishDiagnostics::METHOD.to_string(),
+        PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        },
+    )
+}
+
+fn invalid_params(id: lsp_server::RequestId, err: serde_json::Error) -> Response {
+    Response::new_err(
+        id,
+        lsp_server::ErrorCode::InvalidParams as i32,
+        err.to_string(),
+    )
+}
+
+/// Converts a byte offset into an LSP position, whose character counts UTF-16 code
+/// units.
+fn position(content: &str, offset: usize) -> Position {
+    let before = &content[..offset.min(content.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+/// Converts an LSP position into a byte offset, clamped to the end of its line.
+fn offset(content: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match content[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return content.len(),
+        }
+    }
+    let line = content[line_start..].split('\n').next().unwrap_or_default();
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+fn range(content: &str, bytes: &std::ops::Range<usize>) -> Range {
+    Range {
+        start: position(content, bytes.start),
+        end: position(content, bytes.end),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust::Rust;
+    use lsp_server::RequestId;
+
+    #[test]
+    fn test_session() {
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="email")]
+    email: String,
+}
+
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        email: "jdoe@gmail.com",
+    };
+}
+"#;
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let context = MaskContext::new(&word_pool);
+        let workspace = Workspace::from_paths(vec![], vec![]);
+        let mut session = Session::<Rust, _>::new(Rust::Rs, context, workspace).unwrap();
+        let uri = Url::parse("file:///ws/tests/student.rs").unwrap();
+
+        let published = session.handle_notification(Notification::new(
+            DidOpenTextDocument::METHOD.to_string(),
+            serde_json::json!({
+                "textDocument": {"uri": uri, "languageId": "rust", "version": 1, "text": source},
+            }),
+        ));
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(published[0].params.clone()).unwrap();
+        assert_eq!(params.diagnostics.len(), 1);
+        let diagnostic = &params.diagnostics[0];
+        assert_eq!(diagnostic.message, "Student.email holds an unmasked email");
+        assert_eq!(diagnostic.range.start, Position::new(10, 16));
+        assert_eq!(diagnostic.range.end, Position::new(10, 30));
+
+        let actions = session.code_actions(
+            &uri,
+            Range::new(Position::new(10, 20), Position::new(10, 20)),
+        );
+        assert_eq!(actions.len(), 1);
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected a code action");
+        };
+        let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+        assert_eq!(edits[0].range, diagnostic.range);
+        assert_ne!(edits[0].new_text, "jdoe@gmail.com");
+
+        let response = session.handle_request(Request::new(
+            RequestId::from(1),
+            HoverRequest::METHOD.to_string(),
+            serde_json::json!({
+                "textDocument": {"uri": uri},
+                "position": {"line": 10, "character": 20},
+            }),
+        ));
+        let hover: Hover = serde_json::from_value(response.result.unwrap()).unwrap();
+        let HoverContents::Markup(markup) = hover.contents else {
+            panic!("expected markup");
+        };
+        assert_eq!(markup.value, "`Student.email`: PII category **email**");
+
+        // once the fake is inserted the diagnostic clears, and saving records it
+        let root = std::env::temp_dir().join(format!("pii-masker-lsp-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let fakes_path = root.join(config::FAKES_FILE_NAME);
+        session.fakes_path = Some(fakes_path.clone());
+        let fixed = source.replace("jdoe@gmail.com", &edits[0].new_text);
+        std::fs::write(root.join("student.rs"), &fixed).unwrap();
+        let saved = Url::from_file_path(root.join("student.rs")).unwrap();
+        session.documents.insert(saved.clone(), source.to_string());
+        session.code_actions(&saved, diagnostic.range);
+        let published = session.handle_notification(Notification::new(
+            DidChangeTextDocument::METHOD.to_string(),
+            serde_json::json!({
+                "textDocument": {"uri": saved, "version": 2},
+                "contentChanges": [{"text": fixed}],
+            }),
+        ));
+        let params: PublishDiagnosticsParams =
+            serde_json::from_value(published[0].params.clone()).unwrap();
+        assert!(params.diagnostics.is_empty());
+        session.handle_notification(Notification::new(
+            DidSaveTextDocument::METHOD.to_string(),
+            serde_json::json!({"textDocument": {"uri": saved}}),
+        ));
+        assert_eq!(
+            std::fs::read_to_string(&fakes_path).unwrap(),
+            format!("student.rs\t{}\n", edits[0].new_text)
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_quick_fix_quotes() {
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="ssn")]
+    ssn: u64,
+}
+
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        ssn: 536228745,
+    };
+}
+"#;
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let context = MaskContext::new(&word_pool);
+        let workspace = Workspace::from_paths(vec![], vec![]);
+        let mut session = Session::<Rust, _>::new(Rust::Rs, context, workspace).unwrap();
+        let uri = Url::parse("file:///ws/tests/student.rs").unwrap();
+        session.documents.insert(uri.clone(), source.to_string());
+
+        let actions = session.code_actions(
+            &uri,
+            Range::new(Position::new(10, 20), Position::new(10, 20)),
+        );
+        assert_eq!(actions.len(), 1);
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected a code action");
+        };
+        let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+        assert!(edits[0].new_text.starts_with('"') && edits[0].new_text.ends_with('"'));
+    }
+
+    #[test]
+    fn test_positions() {
+        let content = "ab\nçé\u{1F600}x\n";
+        assert_eq!(position(content, 3), Position::new(1, 0));
+        assert_eq!(position(content, 11), Position::new(1, 4));
+        assert_eq!(offset(content, Position::new(1, 4)), 11);
+        assert_eq!(offset(content, Position::new(5, 0)), content.len());
+    }
+}
@@ -0,0 +1,212 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use pii_masker_pii::{MResult, MaskerError};
+use serde::{Deserialize, Serialize};
+
+use crate::masker::{apply_edits, Replacement};
+use crate::workspace::{MaskedFile, WorkspaceMasking};
+
+/// Whether a masked file is a source, where values are quoted string literals, or a
+/// fixture, where values appear verbatim.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    Source,
+    Fixture,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMapping {
+    pub path: PathBuf,
+    pub kind: FileKind,
+    pub replacements: Vec<Replacement>,
+}
+
+/// The original and fake values of every file touched by a masking run, saved so the
+/// run can be reversed with `restore`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Mapping {
+    pub files: Vec<FileMapping>,
+}
+
+impl From<&WorkspaceMasking> for Mapping {
+    fn from(masking: &WorkspaceMasking) -> Self {
+        let file_mapping = |file: &MaskedFile, kind| FileMapping {
+            path: file.path.clone(),
+            kind,
+            replacements: file.replacements.clone(),
+        };
+        let sources = masking
+            .sources
+            .iter()
+            .map(|file| file_mapping(file, FileKind::Source));
+        let fixtures = masking
+            .fixtures
+            .iter()
+            .map(|file| file_mapping(file, FileKind::Fixture));
+        Self {
+            files: sources.chain(fixtures).collect(),
+        }
+    }
+}
+
+impl Mapping {
+    pub fn load(path: &Path) -> MResult<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+        serde_yaml::from_str(&content)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))
+    }
+
+    pub fn save(&self, path: &Path) -> MResult<()> {
+        let content = serde_yaml::to_string(self)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+        fs::write(path, content)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))
+    }
+}
+
+impl FileMapping {
+    /// Puts the original values back into the masked `content` of this file.
+    ///
+    /// Each value is restored at the position its fake value was written to, which
+    /// fails if the file changed since.
+    pub fn restore(&self, content: &str) -> MResult<String> {
+        let edits = self
+            .replacements
+            .iter()
+            .map(|replacement| {
+                if content.get(replacement.fake_range.clone()) != Some(&replacement.fake) {
+                    return Err(MaskerError::IoError(format!(
+                        "{}: changed since it was masked (bytes {}..{})",
+                        self.path.display(),
+                        replacement.fake_range.start,
+                        replacement.fake_range.end
+                    )));
+                }
+                Ok((replacement.fake_range.clone(), replacement.original.clone()))
+            })
+            .collect::<MResult<Vec<_>>>()?;
+        apply_edits(content, edits)
+    }
+
+    /// Replaces the fake values of the replacements standing as whole tokens in
+    /// `content` with their original values, in a single pass, wherever they are. Where
+    /// several fake values start at the same position, the longest one is restored.
+    pub fn restore_tokens(&self, content: &str) -> String {
+        restore_tokens(content, &self.replacements)
+    }
+}
+
+fn restore_tokens(content: &str, replacements: &[Replacement]) -> String {
+    let edits = find_tokens(content, replacements)
+        .into_iter()
+        .map(|(range, replacement)| (range, replacement.original.clone()))
+        .collect();
+    apply_edits(content, edits).unwrap_or_else(|_| content.to_string())
+}
+
+/// Finds the fake values of `replacements` standing as whole tokens in `content`,
+/// scanning it once from the start. Where several fake values start at the same
+/// position, the longest one is taken. The ranges are sorted and never overlap.
+pub(crate) fn find_tokens<'r>(
+    content: &str,
+    replacements: &'r [Replacement],
+) -> Vec<(Range<usize>, &'r Replacement)> {
+    let mut by_length: Vec<&Replacement> = replacements
+        .iter()
+        .filter(|replacement| !replacement.fake.is_empty())
+        .collect();
+    by_length.sort_by_key(|replacement| std::cmp::Reverse(replacement.fake.len()));
+
+    let mut tokens = vec![];
+    let mut position = 0;
+    while let Some(c) = content[position..].chars().next() {
+        match by_length
+            .iter()
+            .find(|replacement| is_token_at(content, position, &replacement.fake))
+        {
+            Some(replacement) => {
+                tokens.push((position..position + replacement.fake.len(), *replacement));
+                position += replacement.fake.len();
+            }
+            None => position += c.len_utf8(),
+        }
+    }
+    tokens
+}
+
+/// Tells whether `token` appears at byte `position` of `content` without being part of
+/// a longer word.
+pub(crate) fn is_token_at(content: &str, position: usize, token: &str) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    content[position..].starts_with(token)
+        && !(token.starts_with(is_word) && content[..position].ends_with(is_word))
+        && !(token.ends_with(is_word) && content[position + token.len()..].starts_with(is_word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pii_masker_pii::similarity::Category;
+
+    #[test]
+    fn test_restore() {
+        let replacement = |original: &str, fake: &str, fake_range| Replacement {
+            struct_name: "Student".to_string(),
+            field: "last_name".to_string(),
+            category: Category::LastName,
+            inferred: false,
+            range: 0..0,
+            fake_range,
+            line: 1,
+            column: 31,
+            original: original.to_string(),
+            fake: fake.to_string(),
+        };
+        let source = FileMapping {
+            path: PathBuf::from("student.rs"),
+            kind: FileKind::Source,
+            replacements: vec![replacement("Doe", "Col", 30..33)],
+        };
+        // "Col" was masked as "Lee", and "Doe" as "Col"
+        let fixture = FileMapping {
+            path: PathBuf::from("student.yaml"),
+            kind: FileKind::Fixture,
+            replacements: vec![
+                replacement("Doe", "Col", 11..14),
+                replacement("Col", "Lee", 28..31),
+            ],
+        };
+
+        assert_eq!(
+            source
+                .restore("let s = Student { last_name: \"Col\" }; // \"Col\" Column")
+                .unwrap(),
+            "let s = Student { last_name: \"Doe\" }; // \"Col\" Column"
+        );
+        assert!(source.restore("let s = Student {};").is_err());
+        assert_eq!(
+            fixture
+                .restore("last_name: Col\nmaiden_name: Lee\nheader: Column\n")
+                .unwrap(),
+            "last_name: Doe\nmaiden_name: Col\nheader: Column\n"
+        );
+        assert!(fixture.restore("last_name: Lee\n").is_err());
+        // the filter, which sees fixtures whole, restores the fake values as tokens
+        assert_eq!(
+            fixture.restore_tokens("maiden_name: Lee\nlast_name: Col\nheader: Column\n"),
+            "maiden_name: Col\nlast_name: Doe\nheader: Column\n"
+        );
+
+        let mapping = Mapping {
+            files: vec![source, fixture],
+        };
+        let yaml = serde_yaml::to_string(&mapping).unwrap();
+        let loaded: Mapping = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(loaded.files.len(), 2);
+        assert_eq!(loaded.files[1].kind, FileKind::Fixture);
+    }
+}
@@ -1,6 +1,9 @@
-use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Mutex;
 
 use ast_grep_config::{
     DeserializeEnv, GlobalRules, RuleConfig, RuleCore, SerializableRuleConfig,
@@ -9,31 +12,24 @@ use ast_grep_config::{
 use ast_grep_core::meta_var::{MetaVarEnv, MetaVariable};
 use ast_grep_core::{AstGrep, Language, StrDoc};
 use ast_grep_core::{Node as SgNode, NodeMatch as SgNodeMatch};
-use pii_masker_pii::MResult;
+use pii_masker_pii::{MResult, MaskerError};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use pii_masker_pii::similarity::Category;
+use pii_masker_pii::format;
+use pii_masker_pii::similarity::{Category, FakeWordPool};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use sha2::{Digest, Sha256};
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Config {
-    fields: Vec<FieldConfig>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct FieldConfig {
-    regex_pattern: String,
-    source_code_path: String,
-    fixture_file_path: String,
-    field_time: String,
-}
+use crate::key::PseudonymKey;
+use crate::mapping::is_token_at;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Annotation {
     struct_name: String,
     field: String,
     faker: String,
-    #[allow(dead_code)]
     format: Option<String>,
 }
 
@@ -46,6 +42,22 @@ impl Annotation {
             format,
         }
     }
+
+    pub fn struct_name(&self) -> &str {
+        &self.struct_name
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn faker(&self) -> &str {
+        &self.faker
+    }
+
+    pub fn format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
 }
 
 pub struct Expectation<'a, L: Language> {
@@ -55,8 +67,6 @@ pub struct Expectation<'a, L: Language> {
     nm: NodeMatch<'a, L>,
     #[allow(dead_code)]
     grep: &'a AstGrep<StrDoc<L>>,
-    rule_config: RuleConfig<L>,
-    serializable_rule_config: SerializableRuleConfig<L>,
 }
 
 impl<'a, L: Language> Expectation<'a, L> {
@@ -66,8 +76,6 @@ impl<'a, L: Language> Expectation<'a, L> {
         value: String,
         grep: &'a AstGrep<StrDoc<L>>,
         nm: NodeMatch<'a, L>,
-        rule_config: RuleConfig<L>,
-        serializable_rule_config: SerializableRuleConfig<L>,
     ) -> Self {
         Self {
             struct_name,
@@ -75,8 +83,39 @@ impl<'a, L: Language> Expectation<'a, L> {
             value,
             grep,
             nm,
-            rule_config,
-            serializable_rule_config,
+        }
+    }
+
+    pub fn struct_name(&self) -> &str {
+        &self.struct_name
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Zero-based line and byte column of the literal in the source.
+    pub fn start_pos(&self) -> (usize, usize) {
+        self.nm.get_node().start_pos()
+    }
+
+    /// Byte range of the literal in the source, quotes included.
+    pub fn range(&self) -> Range<usize> {
+        self.nm.get_node().range()
+    }
+
+    /// Byte range of the value in the source: the literal without its quotes.
+    pub fn value_range(&self) -> Range<usize> {
+        let node = self.nm.get_node();
+        let range = node.range();
+        if node.text().starts_with('"') && range.len() >= 2 {
+            range.start + 1..range.end - 1
+        } else {
+            range
         }
     }
 }
@@ -158,6 +197,387 @@ impl<'a, L: Language> From<NodeMatch<'a, L>> for MaskerMatch<'a, L> {
     }
 }
 
+/// A single literal rewritten by the masker: the original value found in a test
+/// and the fake value that replaced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replacement {
+    pub struct_name: String,
+    pub field: String,
+    pub category: Category,
+    /// Whether the category was inferred by the classifier rather than annotated.
+    pub inferred: bool,
+    /// Byte range of the original value in the source, quotes excluded.
+    pub range: Range<usize>,
+    /// Byte range of the fake value in the masked source, quotes excluded.
+    #[serde(default)]
+    pub fake_range: Range<usize>,
+    /// One-based line of the literal in the source.
+    pub line: usize,
+    /// One-based byte column of the literal in the source.
+    pub column: usize,
+    pub original: String,
+    pub fake: String,
+}
+
+/// The fake values earlier runs wrote to each file. They are left alone in that file
+/// only, so a real value that happens to equal an old fake is still masked elsewhere.
+#[derive(Debug, Default, Clone)]
+pub struct KnownFakes {
+    files: HashMap<PathBuf, HashSet<String>>,
+}
+
+impl KnownFakes {
+    pub fn insert(&mut self, path: &Path, fake: &str) {
+        self.extend(path, [fake]);
+    }
+
+    pub fn extend<'f>(&mut self, path: &Path, fakes: impl IntoIterator<Item = &'f str>) {
+        self.files
+            .entry(canonical(path))
+            .or_default()
+            .extend(fakes.into_iter().map(str::to_string));
+    }
+
+    /// The fake values known to be written to the file at `path`.
+    pub fn in_file(&self, path: &Path) -> Option<&HashSet<String>> {
+        if self.files.is_empty() {
+            return None;
+        }
+        self.files.get(&canonical(path))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &HashSet<String>)> {
+        self.files
+            .iter()
+            .map(|(path, fakes)| (path.as_path(), fakes))
+    }
+}
+
+/// Paths are compared once resolved, so `./tests/a.rs` and `tests/a.rs` agree.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// A fixture path written in a test, e.g. `include_str!("fixtures/student.yaml")`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixtureReference {
+    pub path: String,
+    /// Whether the path is relative to the directory of the source, as for
+    /// `include_str!`, rather than to the crate root the tests run from.
+    pub relative_to_source: bool,
+}
+
+/// The fake value given to each original value of each category during a run, so
+/// equal values are masked alike in every test, source and fixture.
+#[derive(Debug, Default)]
+pub struct Pseudonyms {
+    table: Mutex<HashMap<(Category, String), String>>,
+}
+
+impl Pseudonyms {
+    /// The fake value given to `original`, if any.
+    pub fn get(&self, category: Category, original: &str) -> Option<String> {
+        let table = self.table.lock().unwrap_or_else(|err| err.into_inner());
+        table.get(&(category, original.to_string())).cloned()
+    }
+
+    /// Returns the fake value given to `original`, or gives it the one `pick` returns.
+    /// Concurrent callers picking for the same value all get the first one recorded.
+    pub fn get_or_insert_with(
+        &self,
+        category: Category,
+        original: &str,
+        pick: impl FnOnce() -> Option<String>,
+    ) -> Option<String> {
+        if let Some(fake) = self.get(category, original) {
+            return Some(fake);
+        }
+        // picking scans the pool, so it is done without holding the lock
+        let fake = pick()?;
+        let mut table = self.table.lock().unwrap_or_else(|err| err.into_inner());
+        Some(
+            table
+                .entry((category, original.to_string()))
+                .or_insert(fake)
+                .clone(),
+        )
+    }
+
+    /// Every fake value given so far.
+    pub fn fakes(&self) -> Vec<String> {
+        let table = self.table.lock().unwrap_or_else(|err| err.into_inner());
+        table.values().cloned().collect()
+    }
+
+    /// Returns the fake value given to `original`, or gives it the one `pick` returns.
+    /// `pick` is told which fake values are already given to other values of the
+    /// category, and runs under the lock, so no concurrent caller takes the same one.
+    pub fn get_or_insert_unique(
+        &self,
+        category: Category,
+        original: &str,
+        pick: impl FnOnce(&dyn Fn(&str) -> bool) -> Option<String>,
+    ) -> Option<String> {
+        let mut table = self.table.lock().unwrap_or_else(|err| err.into_inner());
+        let key = (category, original.to_string());
+        if let Some(fake) = table.get(&key) {
+            return Some(fake.clone());
+        }
+        let fake = {
+            let taken: HashSet<&str> = table
+                .iter()
+                .filter(|((taken_category, _), _)| *taken_category == category)
+                .map(|(_, fake)| fake.as_str())
+                .collect();
+            pick(&|word| taken.contains(word))?
+        };
+        table.insert(key, fake.clone());
+        Some(fake)
+    }
+
+    /// Records the `(category, original, fake)` values of a result computed in an
+    /// earlier run, unless one of them disagrees with the fake value already given to
+    /// its original value. Tells whether they were recorded.
+    pub fn record(&self, fakes: Vec<(Category, String, String)>) -> bool {
+        let mut table = self.table.lock().unwrap_or_else(|err| err.into_inner());
+        let mut recorded: HashMap<(Category, String), String> = HashMap::new();
+        for (category, original, fake) in fakes {
+            let key = (category, original);
+            let known = table.get(&key).or(recorded.get(&key));
+            if known.is_some_and(|known| *known != fake) {
+                return false;
+            }
+            recorded.insert(key, fake);
+        }
+        table.extend(recorded);
+        true
+    }
+}
+
+/// Settings shared by every file masked or checked in one run.
+pub struct MaskContext<'a> {
+    pub word_pool: &'a FakeWordPool,
+    /// Only values of these categories are masked, every category when empty.
+    pub categories: HashSet<Category>,
+    /// Values known not to be real PII, which are left untouched.
+    pub allowlist: HashSet<String>,
+    /// The fake values written by earlier runs, which are left untouched in their file.
+    pub known_fakes: KnownFakes,
+    /// The fake values given so far, reused for every occurrence of the same value.
+    pub pseudonyms: Pseudonyms,
+    /// Picks the fake values with this key rather than by similarity, so they only
+    /// depend on the key, the category and the original values.
+    pub key: Option<PseudonymKey>,
+    /// Generates the fake values of structured identifiers, such as SSNs or emails,
+    /// with the shape of the original ones rather than picking them from the pool.
+    pub format_preserving: bool,
+}
+
+impl<'a> MaskContext<'a> {
+    pub fn new(word_pool: &'a FakeWordPool) -> Self {
+        Self {
+            word_pool,
+            categories: HashSet::new(),
+            allowlist: HashSet::new(),
+            known_fakes: KnownFakes::default(),
+            pseudonyms: Pseudonyms::default(),
+            key: None,
+            format_preserving: false,
+        }
+    }
+
+    /// A context with the word pool and settings of this one but no fake value given
+    /// yet, so the values it masks are forgotten along with it.
+    pub fn fresh(&self) -> MaskContext<'a> {
+        Self {
+            word_pool: self.word_pool,
+            categories: self.categories.clone(),
+            allowlist: self.allowlist.clone(),
+            known_fakes: self.known_fakes.clone(),
+            pseudonyms: Pseudonyms::default(),
+            key: self.key.clone(),
+            format_preserving: self.format_preserving,
+        }
+    }
+
+    /// Returns the category of a field annotated with `faker` holding `value`, and
+    /// whether it had to be inferred by the classifier.
+    pub fn resolve_category(&self, faker: &str, value: &str) -> MResult<(Category, bool)> {
+        let category = Category::from_str(faker)?;
+        if category == Category::Inferred {
+            let classification =
+                pii_masker_pii::similarity::classify_word(value, self.word_pool, 1)?;
+            return Ok((classification.category, true));
+        }
+        Ok((category, false))
+    }
+
+    /// Returns the fake value already given to `value` in this run, or else picks the
+    /// fake value of `category` in the pool with the key, or the one most similar to
+    /// `value` without a key. `None` when the pool holds no value of that category.
+    ///
+    /// With a key, a pool value already given to another value of the category is
+    /// skipped while the pool has others, so values only share a fake once every value
+    /// of the category in the pool is taken. Which value gets a contested pool value
+    /// depends on the order they are given fakes in, see `assign_fakes`.
+    pub fn fake_for(&self, value: &str, category: Category) -> Option<String> {
+        let structured = self.format_preserving && format::is_structured(category);
+        if let (Some(key), false) = (&self.key, structured) {
+            return self
+                .pseudonyms
+                .get_or_insert_unique(category, value, |taken| {
+                    key.pick(category, value, self.words(category)?, taken)
+                        .cloned()
+                });
+        }
+        self.pseudonyms.get_or_insert_with(category, value, || {
+            if structured {
+                let mut rng = StdRng::from_seed(self.format_seed(value, category));
+                if let Some(fake) = format::format_preserving_fake(value, category, &mut rng) {
+                    return Some(fake);
+                }
+            }
+            let words = self.words(category)?;
+            if let Some(key) = &self.key {
+                return key.pick(category, value, words, |_| false).cloned();
+            }
+            pii_masker_pii::similarity::sample_similar_word_for_category(
+                value,
+                category,
+                self.word_pool,
+                1,
+            )
+            .into_iter()
+            .next()
+        })
+    }
+
+    /// Gives fake values to `values` one at a time, sorted, before files are masked
+    /// concurrently. With a key, the first value to pick a pool value gets it, so
+    /// picking them in a fixed order makes the fakes of a run independent of which
+    /// file is masked first.
+    pub fn assign_fakes(&self, mut values: Vec<(Category, String)>) {
+        values.sort_by_cached_key(|(category, value)| (category.to_string(), value.clone()));
+        values.dedup();
+        for (category, value) in values {
+            self.fake_for(&value, category);
+        }
+    }
+
+    /// The values of `category` in the pool, `None` when there are none.
+    fn words(&self, category: Category) -> Option<&Vec<String>> {
+        self.word_pool
+            .iter()
+            .find(|(pool_category, words)| *pool_category == category && !words.is_empty())
+            .map(|(_, words)| words)
+    }
+
+    /// The seed of the format-preserving fake value of `value`: derived from the key, or
+    /// else from the words of the pool, so the fake value is the same on every run with
+    /// the same key or seeded pool.
+    fn format_seed(&self, value: &str, category: Category) -> [u8; 32] {
+        if let Some(key) = &self.key {
+            return key.rng_seed(category, value);
+        }
+        let mut hasher = Sha256::new();
+        for part in [category.to_string().as_str(), value] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+        let words = self
+            .word_pool
+            .iter()
+            .filter(|(pool_category, _)| *pool_category == category)
+            .flat_map(|(_, words)| words);
+        for word in words {
+            hasher.update(word.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.finalize().into()
+    }
+
+    /// Tells whether values of `category` are selected for masking.
+    pub fn masks_category(&self, category: Category) -> bool {
+        self.categories.is_empty() || self.categories.contains(&category)
+    }
+}
+
+/// Replaces the masked values standing as whole tokens in a fixture, in a single pass,
+/// so a value inside a longer word, or inside the fake value of another one, is left
+/// alone. Returns the masked fixture and a replacement for every occurrence replaced,
+/// with its ranges, line and column in the fixture.
+pub fn mask_fixture(fixture: &str, replacements: &[Replacement]) -> (String, Vec<Replacement>) {
+    let mut seen = HashSet::new();
+    let mut occurrences = vec![];
+    for replacement in replacements {
+        if replacement.original.is_empty() || !seen.insert(&replacement.original) {
+            continue;
+        }
+        let Ok(re) = Regex::new(&regex::escape(&replacement.original)) else {
+            continue;
+        };
+        for found in re.find_iter(fixture) {
+            if !is_token_at(fixture, found.start(), &replacement.original) {
+                continue;
+            }
+            let line_start = fixture[..found.start()].rfind('\n').map_or(0, |i| i + 1);
+            occurrences.push(Replacement {
+                range: found.range(),
+                line: fixture[..found.start()].matches('\n').count() + 1,
+                column: found.start() - line_start + 1,
+                ..replacement.clone()
+            });
+        }
+    }
+    // of the values found at the same position, the longest one is replaced
+    occurrences.sort_by_key(|occurrence| std::cmp::Reverse(occurrence.range.len()));
+    let masked = apply_replacements(fixture, &mut occurrences);
+    (masked, occurrences)
+}
+
+/// Replaces byte ranges of `content` in a single pass, so the replacements can have any
+/// length. The edits are sorted first; overlapping edits, or ranges that do not fall on
+/// character boundaries of `content`, are an error.
+pub fn apply_edits(content: &str, mut edits: Vec<(Range<usize>, String)>) -> MResult<String> {
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut applied = String::with_capacity(content.len());
+    let mut last = 0;
+    for (range, text) in edits {
+        if range.start < last || content.get(range.clone()).is_none() {
+            return Err(MaskerError::RuleParseError(format!(
+                "invalid edit of bytes {}..{}",
+                range.start, range.end
+            )));
+        }
+        applied.push_str(&content[last..range.start]);
+        applied.push_str(&text);
+        last = range.end;
+    }
+    applied.push_str(&content[last..]);
+    Ok(applied)
+}
+
+/// Splices the fake values into `content` at the byte ranges of the replacements, and
+/// records where each of them ends up. Replacements overlapping an earlier one are
+/// dropped, the others sorted by position.
+pub fn apply_replacements(content: &str, replacements: &mut Vec<Replacement>) -> String {
+    replacements.sort_by_key(|replacement| replacement.range.start);
+    let mut applied = String::with_capacity(content.len());
+    let mut last = 0;
+    replacements.retain_mut(|replacement| {
+        if replacement.range.start < last {
+            return false;
+        }
+        applied.push_str(&content[last..replacement.range.start]);
+        replacement.fake_range = applied.len()..applied.len() + replacement.fake.len();
+        applied.push_str(&replacement.fake);
+        last = replacement.range.end;
+        true
+    });
+    applied.push_str(&content[last..]);
+    applied
+}
+
 pub trait PIIMaskable<L: Language> {
     fn mask_tests(
         language: L,
@@ -166,91 +586,154 @@ pub trait PIIMaskable<L: Language> {
         category_pool_size: usize,
     ) -> MResult<(String, String)> {
         let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(category_pool_size);
+        Self::mask_tests_with(language, source, fixture, &MaskContext::new(&word_pool))
+    }
+
+    /// Like `mask_tests`, drawing the fake values from the pool of `context`, so a pool
+    /// can be generated once and reused.
+    fn mask_tests_with(
+        language: L,
+        source: &str,
+        fixture: &str,
+        context: &MaskContext,
+    ) -> MResult<(String, String)> {
         let mut new_source = source.to_string();
         let mut new_fixture = fixture.to_string();
         if let Ok(annotations) = Self::discover_struct_annotations(&language, source) {
-            let grep = language.ast_grep(source);
-            if let Ok(expectations) = Self::discover_expectations(language, &grep) {
-                for expectation in expectations {
-                    if let Some(faker) = Self::lookup_faker_for_field(
-                        &expectation.struct_name,
-                        &expectation.field,
-                        &annotations,
-                    ) {
-                        let fake_value = pii_masker_pii::similarity::sample_similar_word_for_category(
-                            &expectation.value,
-                            Category::from_str(faker).unwrap(),
-                            &word_pool,
-                            1,
-                        );
-                        let mut fake_value_processed =
-                            fake_value.iter().next().unwrap().to_string();
-
-                        // if the faked word's length is less than the oringinal one path with
-                        // whitespaces
-                        // if it's longer, truncate it
-                        let expectation_value_len = expectation.value.len();
-                        match fake_value_processed.len().cmp(&expectation_value_len) {
-                            Ordering::Less => {
-                                let whitespaces = " "
-                                    .repeat(expectation.value.len() - fake_value_processed.len());
-                                fake_value_processed =
-                                    format!("{}{}", fake_value_processed, whitespaces);
-                            }
-                            Ordering::Greater => {
-                                fake_value_processed =
-                                    fake_value_processed[..expectation.value.len()].to_string();
-                            }
-                            Ordering::Equal => {}
-                        }
+            let (masked_source, replacements) =
+                Self::mask_source(language, source, &annotations, context)?;
+            new_source = masked_source;
+            new_fixture = mask_fixture(fixture, &replacements).0;
+        }
 
-                        if fake_value_processed.len() > expectation.value.len() {
-                            fake_value_processed =
-                                fake_value_processed[..expectation.value.len()].to_string();
-                        }
+        Ok((new_source, new_fixture))
+    }
 
-                        let matcher = &expectation.rule_config.matcher;
-
-                        let globals = GlobalRules::default();
-                        let mut mutable_serializable_rule_config =
-                            expectation.serializable_rule_config.clone();
-                        //
-                        // Note: if the fake value contains only numeric characters, Serde will not
-                        // return the correct type back. By wrapping it in quotes, we can ensure that
-                        // the type is correctly inferred.
-                        let fake_value_with_quotes = format!("\"{}\"", fake_value_processed);
-                        mutable_serializable_rule_config.fix =
-                            Some(ast_grep_config::from_str(&fake_value_with_quotes).unwrap());
-                        let rule_config = RuleConfig::try_from(
-                            mutable_serializable_rule_config.clone(),
-                            &globals,
-                        )
-                        .unwrap();
-                        let fixer = &rule_config.get_fixer().unwrap().unwrap();
-                        let edit = expectation.nm.make_edit(matcher, fixer);
-                        let mut start = 0;
-                        let mut new_content = Vec::<char>::new();
-                        let src: Vec<_> = new_source.chars().collect();
-                        let inserted_text = String::from_utf8(edit.inserted_text).unwrap();
-                        let inserted_text = format!("\"{}\"", inserted_text);
-                        let inserted_text = inserted_text.chars();
-                        new_content.extend(&src[start..edit.position]);
-                        new_content.extend(inserted_text);
-                        start = edit.position + edit.deleted_length;
-                        new_content.extend(&src[start..]);
-                        new_source = new_content.iter().collect::<String>();
-
-                        if let Ok(re) = Regex::new(&expectation.value) {
-                            new_fixture = re
-                                .replace_all(&new_fixture, fake_value_processed)
-                                .to_string();
-                        }
-                    }
-                }
+    /// Masks the test expectations of `source` whose struct fields are annotated in
+    /// `annotations`. The annotations do not need to come from `source` itself, which
+    /// allows structs declared in one file to be masked in the tests of another.
+    fn mask_source(
+        language: L,
+        source: &str,
+        annotations: &Vec<Annotation>,
+        context: &MaskContext,
+    ) -> MResult<(String, Vec<Replacement>)> {
+        Self::mask_source_at(language, None, source, annotations, context)
+    }
+
+    /// Like `mask_source` for the source read from `path`, leaving alone the fake
+    /// values earlier runs wrote to it.
+    fn mask_source_at(
+        language: L,
+        path: Option<&Path>,
+        source: &str,
+        annotations: &Vec<Annotation>,
+        context: &MaskContext,
+    ) -> MResult<(String, Vec<Replacement>)> {
+        let mut edits = vec![];
+        let mut replacements = vec![];
+        let grep = language.ast_grep(source);
+        for (expectation, category, inferred) in
+            Self::masked_expectations(language, path, &grep, annotations, context)?
+        {
+            let fake = context
+                .fake_for(&expectation.value, category)
+                .ok_or_else(|| {
+                    MaskerError::SimilarityError(format!(
+                        "No fake {} available for {}.{}",
+                        category, expectation.struct_name, expectation.field
+                    ))
+                })?;
+
+            // a string literal keeps its quotes, other literals become strings
+            let range = expectation.value_range();
+            if range == expectation.range() {
+                edits.push((range.clone(), format!("\"{}\"", fake)));
+            } else {
+                edits.push((range.clone(), fake.clone()));
             }
+            let (line, column) = expectation.start_pos();
+            replacements.push(Replacement {
+                struct_name: expectation.struct_name.clone(),
+                field: expectation.field.clone(),
+                category,
+                inferred,
+                range,
+                fake_range: 0..0,
+                line: line + 1,
+                column: column + 1,
+                original: expectation.value.clone(),
+                fake,
+            });
         }
 
-        Ok((new_source, new_fixture))
+        // the fake values move by the change of length of the edits before them
+        let mut order: Vec<usize> = (0..edits.len()).collect();
+        order.sort_by_key(|&index| edits[index].0.start);
+        let mut shift = 0isize;
+        for index in order {
+            let (range, text) = &edits[index];
+            let replacement = &mut replacements[index];
+            let quote = usize::from(text.len() != replacement.fake.len());
+            let start = range.start.saturating_add_signed(shift) + quote;
+            replacement.fake_range = start..start + replacement.fake.len();
+            shift += text.len() as isize - range.len() as isize;
+        }
+
+        let new_source = apply_edits(source, edits)?;
+        Ok((new_source, replacements))
+    }
+
+    /// Lists the `(category, value)` of the expectations of the source at `path` that
+    /// `mask_source_at` masks, without giving them fake values.
+    fn masked_values(
+        language: L,
+        path: &Path,
+        source: &str,
+        annotations: &Vec<Annotation>,
+        context: &MaskContext,
+    ) -> MResult<Vec<(Category, String)>> {
+        let grep = language.ast_grep(source);
+        let masked = Self::masked_expectations(language, Some(path), &grep, annotations, context)?;
+        Ok(masked
+            .into_iter()
+            .map(|(expectation, category, _)| (category, expectation.value))
+            .collect())
+    }
+
+    /// The expectations of `grep` whose field is annotated in `annotations` and whose
+    /// value is masked under `context`, with its category and whether it was inferred.
+    fn masked_expectations<'g>(
+        language: L,
+        path: Option<&Path>,
+        grep: &'g AstGrep<StrDoc<L>>,
+        annotations: &Vec<Annotation>,
+        context: &MaskContext,
+    ) -> MResult<Vec<(Expectation<'g, L>, Category, bool)>> {
+        let mut masked = vec![];
+        let Ok(expectations) = Self::discover_expectations(language, grep) else {
+            return Ok(masked);
+        };
+        let known_fakes = path.and_then(|path| context.known_fakes.in_file(path));
+        for expectation in expectations {
+            let Some(faker) = Self::lookup_faker_for_field(
+                &expectation.struct_name,
+                &expectation.field,
+                annotations,
+            ) else {
+                continue;
+            };
+            if context.allowlist.contains(&expectation.value)
+                || known_fakes.is_some_and(|fakes| fakes.contains(&expectation.value))
+            {
+                continue;
+            }
+            let (category, inferred) = context.resolve_category(faker, &expectation.value)?;
+            if context.masks_category(category) {
+                masked.push((expectation, category, inferred));
+            }
+        }
+        Ok(masked)
     }
 
     fn discover_struct_annotations(language: &L, source: &str) -> MResult<Vec<Annotation>> {
@@ -282,7 +765,7 @@ pub trait PIIMaskable<L: Language> {
     fn discover_expectations(
         language: L,
         grep: &AstGrep<StrDoc<L>>,
-    ) -> MResult<Vec<Expectation<L>>> {
+    ) -> MResult<Vec<Expectation<'_, L>>> {
         let mut expectations = vec![];
         if let Ok(serializable_rule_config) = Self::make_expectations_discovery_matcher(language) {
             let env = DeserializeEnv::new(serializable_rule_config.language.clone());
@@ -296,9 +779,6 @@ pub trait PIIMaskable<L: Language> {
                 if let Some(struct_name) = nm2.env.get("STRUCT") {
                     if let Some(field) = nm2.env.get("FIELD") {
                         if let Some(value) = nm2.env.get("VALUE") {
-                            let rule_config =
-                                RuleConfig::try_from(serializable_rule_config.clone(), &globals)
-                                    .unwrap();
                             let value = value.text.replace('"', "");
                             let expectation = Expectation::new(
                                 struct_name.text.clone(),
@@ -306,8 +786,6 @@ pub trait PIIMaskable<L: Language> {
                                 value,
                                 grep,
                                 nm.clone(),
-                                rule_config,
-                                serializable_rule_config.clone(),
                             );
                             expectations.push(expectation);
                         }
@@ -318,7 +796,19 @@ pub trait PIIMaskable<L: Language> {
         Ok(expectations)
     }
 
-    fn make_struct_annotations_matcher(language: L) -> MResult<RuleCore<L>>;
+    /// Finds the fixture paths referenced from the tests of `source`. Languages that
+    /// cannot tell fixture paths apart return none.
+    fn discover_fixture_references(_language: &L, _source: &str) -> MResult<Vec<FixtureReference>> {
+        Ok(vec![])
+    }
+
+    /// The rule matching the `#[pii_mask]` annotations of struct fields.
+    fn make_struct_annotations_rule() -> MResult<SerializableRuleCore>;
+    fn make_struct_annotations_matcher(language: L) -> MResult<RuleCore<L>> {
+        Self::make_struct_annotations_rule()?
+            .get_matcher(DeserializeEnv::new(language))
+            .map_err(|_| MaskerError::RuleParseError("Failed to produce matcher".to_string()))
+    }
     fn make_expectations_discovery_matcher(language: L) -> MResult<SerializableRuleConfig<L>>;
     fn lookup_faker_for_field<'a>(
         struct_name: &str,
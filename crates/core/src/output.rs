@@ -0,0 +1,72 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use pii_masker_pii::{MResult, MaskerError};
+
+/// Replaces the content of `path` atomically: the content is written to a temporary
+/// file in the same directory, flushed to disk and renamed over the original, so a
+/// reader never observes a partially written file.
+pub fn write_atomic(path: &Path, content: impl AsRef<[u8]>) -> MResult<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| MaskerError::IoError(format!("{}: not a file", path.display())))?;
+    let tmp_path = path.with_file_name(format!(
+        ".{}.pii-masker-{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| {
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(content.as_ref())?;
+        tmp.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&tmp_path, metadata.permissions())?;
+        }
+        fs::rename(&tmp_path, path)
+    })();
+
+    result.map_err(|err| {
+        let _ = fs::remove_file(&tmp_path);
+        MaskerError::IoError(format!("{}: {}", path.display(), err))
+    })
+}
+
+/// Copies `path` next to itself with `suffix` appended to its file name, e.g.
+/// `student.rs` becomes `student.rs.orig`, and returns the backup path.
+pub fn backup(path: &Path, suffix: &str) -> MResult<PathBuf> {
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(suffix);
+    let backup_path = PathBuf::from(backup_path);
+    fs::copy(path, &backup_path)
+        .map_err(|err| MaskerError::IoError(format!("{}: {}", backup_path.display(), err)))?;
+    Ok(backup_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic_with_backup() {
+        let dir = std::env::temp_dir().join(format!("pii-masker-output-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("student.yaml");
+        fs::write(&path, "first_name: John\n").unwrap();
+
+        let backup_path = backup(&path, ".orig").unwrap();
+        write_atomic(&path, "first_name: Jane\n").unwrap();
+
+        assert_eq!(backup_path, dir.join("student.yaml.orig"));
+        assert_eq!(
+            fs::read_to_string(&backup_path).unwrap(),
+            "first_name: John\n"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first_name: Jane\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
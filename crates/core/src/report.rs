@@ -0,0 +1,157 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use pii_masker_pii::similarity::Category;
+use pii_masker_pii::{MResult, MaskerError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::masker::Replacement;
+use crate::workspace::WorkspaceMasking;
+
+/// An audit trail of a masking run, listing every literal replaced in the sources.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub root: PathBuf,
+    /// Whether `original` holds a SHA-256 digest of the value rather than the value.
+    pub hashed_originals: bool,
+    pub replacements: Vec<ReportEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportEntry {
+    /// The source holding the literal, relative to the root.
+    pub file: PathBuf,
+    /// Byte range of the original value in the source, quotes excluded.
+    pub byte_range: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+    pub struct_name: String,
+    pub field: String,
+    pub category: Category,
+    pub inferred: bool,
+    pub original: String,
+    pub replacement: String,
+    /// The fixtures, relative to the root, in which the same value was replaced.
+    pub fixtures: Vec<PathBuf>,
+}
+
+impl Report {
+    /// Builds the report of `masking`, replacing the originals by their SHA-256 digest
+    /// when `hash_originals` is set.
+    pub fn new(root: &Path, masking: &WorkspaceMasking, hash_originals: bool) -> Self {
+        let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_path_buf();
+        let mut replacements = vec![];
+        for source in &masking.sources {
+            for replacement in &source.replacements {
+                let fixtures = masking
+                    .fixtures
+                    .iter()
+                    .filter(|fixture| {
+                        fixture.replacements.iter().any(|other| {
+                            other.original == replacement.original && other.fake == replacement.fake
+                        })
+                    })
+                    .map(|fixture| relative(&fixture.path))
+                    .collect();
+                replacements.push(ReportEntry::new(
+                    relative(&source.path),
+                    replacement,
+                    hash_originals,
+                    fixtures,
+                ));
+            }
+        }
+        Self {
+            root: root.to_path_buf(),
+            hashed_originals: hash_originals,
+            replacements,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> MResult<()> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+        fs::write(path, content + "\n")
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))
+    }
+}
+
+impl ReportEntry {
+    fn new(
+        file: PathBuf,
+        replacement: &Replacement,
+        hash_original: bool,
+        fixtures: Vec<PathBuf>,
+    ) -> Self {
+        let original = if hash_original {
+            format!(
+                "sha256:{}",
+                hex::encode(Sha256::digest(replacement.original.as_bytes()))
+            )
+        } else {
+            replacement.original.clone()
+        };
+        Self {
+            file,
+            byte_range: replacement.range.clone(),
+            line: replacement.line,
+            column: replacement.column,
+            struct_name: replacement.struct_name.clone(),
+            field: replacement.field.clone(),
+            category: replacement.category,
+            inferred: replacement.inferred,
+            original,
+            replacement: replacement.fake.clone(),
+            fixtures,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workspace::MaskedFile;
+
+    #[test]
+    fn test_report() {
+        let replacement = Replacement {
+            struct_name: "Student".to_string(),
+            field: "email".to_string(),
+            category: Category::Email,
+            inferred: false,
+            range: 120..134,
+            fake_range: 120..134,
+            line: 12,
+            column: 16,
+            original: "jdoe@gmail.com".to_string(),
+            fake: "joe@example.co".to_string(),
+        };
+        let masked_file = |path: &str| MaskedFile {
+            path: PathBuf::from(path),
+            original: String::new(),
+            masked: String::new(),
+            replacements: vec![replacement.clone()],
+        };
+        let masking = WorkspaceMasking {
+            sources: vec![masked_file("/ws/tests/student.rs")],
+            fixtures: vec![masked_file("/ws/fixtures/student.yaml")],
+        };
+
+        let report = Report::new(Path::new("/ws"), &masking, false);
+        let entry = &report.replacements[0];
+        assert_eq!(entry.file, PathBuf::from("tests/student.rs"));
+        assert_eq!(entry.original, "jdoe@gmail.com");
+        assert_eq!(entry.fixtures, vec![PathBuf::from("fixtures/student.yaml")]);
+
+        let json = serde_json::to_value(Report::new(Path::new("/ws"), &masking, true)).unwrap();
+        let entry = &json["replacements"][0];
+        assert_eq!(entry["category"], "email");
+        assert_eq!(entry["byte_range"]["start"], 120);
+        assert_eq!(
+            entry["original"],
+            "sha256:910789803908d9ab8a57b28ff0af7ab81b9607356aef6f18d8499eb1489ed8ab"
+        );
+    }
+}
@@ -7,7 +7,7 @@ use ast_grep_core::{language::TSLanguage, Language};
 use pii_masker_inspect::DerivePIIMaskArgs;
 use serde::Deserialize;
 
-use crate::masker::{Annotation, MaskerMatch, PIIMaskable};
+use crate::masker::{Annotation, FixtureReference, MaskerMatch, PIIMaskable};
 use pii_masker_pii::{MResult, MaskerError};
 
 use darling::FromDeriveInput;
@@ -59,10 +59,10 @@ impl<L: Language> PIIMaskable<L> for Rust {
                         if let Some(struct_item) = nm2.env.get("B") {
                             let attr_and_struct =
                                 format!("{} {}", attribute_item.text, struct_item.text);
-                            let input =
-                                parse_str::<DeriveInput>(&attr_and_struct).expect("should parse");
+                            let input = parse_str::<DeriveInput>(&attr_and_struct)
+                                .map_err(|err| MaskerError::RuleParseError(err.to_string()))?;
                             let parsed_args = DerivePIIMaskArgs::from_derive_input(&input)
-                                .expect("should convert");
+                                .map_err(|err| MaskerError::RuleParseError(err.to_string()))?;
 
                             let struct_name = parsed_args.ident.to_string();
 
@@ -92,12 +92,61 @@ impl<L: Language> PIIMaskable<L> for Rust {
         Ok(annotations)
     }
 
-    fn make_struct_annotations_matcher(
-        language: L,
-    ) -> MResult<ast_grep_config::RuleCore<L>> {
-        let env = DeserializeEnv::new(language);
+    fn discover_fixture_references(language: &L, source: &str) -> MResult<Vec<FixtureReference>> {
+        let env = DeserializeEnv::new(language.clone());
         let ser_rule = ast_grep_config::from_str::<SerializableRuleCore>(
             r#"
+rule:
+  kind: string_literal
+  pattern: $PATH
+  any:
+    - inside:
+        kind: token_tree
+        inside:
+          kind: macro_invocation
+          regex: ^include_(str|bytes)!
+          pattern: $INCLUDE
+    - inside:
+        kind: arguments
+        inside:
+          kind: call_expression
+          regex: \b(fs::read_to_string|fs::read|File::open|Path::new|PathBuf::from)\s*\(
+  inside:
+    kind: function_item
+    follows:
+      kind: attribute_item
+      regex: ^#\[(\w+::)*test\b
+      stopBy:
+        not:
+          any:
+            - kind: attribute_item
+            - kind: line_comment
+            - kind: block_comment
+    stopBy: end
+"#,
+        )
+        .map_err(|err| MaskerError::RuleParseError(err.to_string()))?;
+        let matcher = ser_rule
+            .get_matcher(env)
+            .map_err(|err| MaskerError::RuleParseError(err.to_string()))?;
+
+        let mut references = vec![];
+        let grep = language.ast_grep(source);
+        for nm in grep.root().find_all(&matcher) {
+            let nm2 = MaskerMatch::from(nm);
+            if let Some(path) = nm2.env.get("PATH") {
+                references.push(FixtureReference {
+                    path: path.text.trim_matches('"').to_string(),
+                    relative_to_source: nm2.env.contains_key("INCLUDE"),
+                });
+            }
+        }
+        Ok(references)
+    }
+
+    fn make_struct_annotations_rule() -> MResult<SerializableRuleCore> {
+        ast_grep_config::from_str::<SerializableRuleCore>(
+            r#"
 rule:
   any:
     - kind: attribute_item
@@ -124,20 +173,8 @@ rule:
               kind: type_identifier
               pattern: $STRUCT
 "#,
-        );
-        if let Ok(ser_rule) = ser_rule {
-            if let Ok(matcher) = ser_rule.get_matcher(env) {
-                Ok(matcher)
-            } else {
-                Err(MaskerError::RuleParseError(
-                    "Failed to produce matcher".to_string(),
-                ))
-            }
-        } else {
-            Err(MaskerError::RuleParseError(
-                "Failed to parse rule".to_string(),
-            ))
-        }
+        )
+        .map_err(|_| MaskerError::RuleParseError("Failed to parse rule".to_string()))
     }
 
     fn make_expectations_discovery_matcher(
@@ -250,6 +287,8 @@ rule:
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::masker::MaskContext;
+    use pii_masker_pii::similarity::Category;
 
     #[test]
     fn test_rust_literal_writing_to_env() {
@@ -306,6 +345,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_discover_fixture_references() {
+        let source = r#"
+const SHARED: &str = include_str!("fixtures/shared.yaml");
+
+#[test]
+fn test_lookup_student() {
+    let student: Student = serde_yaml::from_str(include_str!("fixtures/student.yaml")).unwrap();
+    let courses = std::fs::read_to_string("tests/fixtures/courses.json").unwrap();
+    let grades = Path::new("tests/fixtures/grades.csv");
+    println!("{}", "not a fixture");
+    reader.read("not a fixture either");
+}
+
+fn test_helper() {
+    let helper = std::fs::read_to_string("tests/fixtures/helper.json").unwrap();
+}
+
+#[test]
+// reads raw bytes
+#[should_panic]
+fn lookup_missing_student() {
+    let bytes = std::fs::read("tests/fixtures/missing.bin").unwrap();
+}
+"#;
+        let references = Rust::discover_fixture_references(&Rust::Rs, source).unwrap();
+        let reference = |path: &str, relative_to_source| FixtureReference {
+            path: path.to_string(),
+            relative_to_source,
+        };
+        assert_eq!(
+            references,
+            vec![
+                reference("fixtures/student.yaml", true),
+                reference("tests/fixtures/courses.json", false),
+                reference("tests/fixtures/grades.csv", false),
+                reference("tests/fixtures/missing.bin", false),
+            ]
+        );
+    }
+
     #[test]
     fn test_mask_tests() {
         let source = r#"
@@ -354,4 +434,98 @@ student:
         assert_eq!(masked.as_ref().unwrap().0.contains("123-45-6789"), false);
         assert_eq!(masked.as_ref().unwrap().0.contains("310-444-2211"), false);
     }
+
+    #[test]
+    fn test_mask_consistent_pseudonyms() {
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="first_name")]
+    first_name: String,
+}
+
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        first_name: "John",
+    };
+}
+
+#[test]
+fn test_lookup_other_student() {
+    let expected_student = Student {
+        first_name: "John",
+    };
+}
+"#;
+        // a pool generated without a seed, so only the pseudonyms make the fakes agree
+        // without John, who would be the closest fake to John
+        let mut word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        for (_, words) in word_pool.iter_mut() {
+            words.retain(|word| word != "John");
+        }
+        let context = MaskContext::new(&word_pool);
+        assert!(context.pseudonyms.get(Category::FirstName, "John").is_none());
+
+        // two files masked in the same run
+        let annotations = Rust::discover_struct_annotations(&Rust::Rs, source).unwrap();
+        let mut fakes = vec![];
+        for _ in 0..2 {
+            let (masked, replacements) =
+                Rust::mask_source(Rust::Rs, source, &annotations, &context).unwrap();
+            assert_eq!(replacements.len(), 2);
+            assert!(!masked.contains("\"John\""));
+            fakes.extend(replacements.into_iter().map(|replacement| replacement.fake));
+        }
+        // masking recorded the first fake given to John, and every occurrence reused it
+        let fake = context.pseudonyms.get(Category::FirstName, "John").unwrap();
+        assert!(fakes.iter().all(|other| *other == fake));
+        let (_, fixture) =
+            Rust::mask_tests_with(Rust::Rs, source, "first_name: John\n", &context).unwrap();
+        assert_eq!(fixture, format!("first_name: {}\n", fake));
+    }
+
+    #[test]
+    fn test_mask_variable_length_unicode() {
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="first_name")]
+    first_name: String,
+    #[pii_mask(faker="last_name")]
+    last_name: String,
+}
+
+// élève: ünïcödé before the literals
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        first_name: "Zoë",
+        last_name: "Doe",
+    };
+}
+"#;
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let context = MaskContext::new(&word_pool);
+        context
+            .pseudonyms
+            .get_or_insert_with(Category::FirstName, "Zoë", || {
+                Some("Maximilian".to_string())
+            });
+        context
+            .pseudonyms
+            .get_or_insert_with(Category::LastName, "Doe", || Some("Li".to_string()));
+
+        let annotations = Rust::discover_struct_annotations(&Rust::Rs, source).unwrap();
+        let (masked, replacements) =
+            Rust::mask_source(Rust::Rs, source, &annotations, &context).unwrap();
+        assert!(masked.contains("first_name: \"Maximilian\",\n"));
+        assert!(masked.contains("last_name: \"Li\",\n"));
+        assert!(masked.contains("// élève: ünïcödé before the literals"));
+        assert_eq!(replacements.len(), 2);
+        for replacement in &replacements {
+            assert_eq!(source[replacement.range.clone()], replacement.original);
+            assert_eq!(masked[replacement.fake_range.clone()], replacement.fake);
+        }
+    }
 }
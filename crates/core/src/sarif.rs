@@ -0,0 +1,208 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::check::Finding;
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Returns the SARIF rule id of the findings of a category, e.g. `pii/ssn`.
+pub fn rule_id(finding: &Finding) -> String {
+    format!("pii/{}", finding.category)
+}
+
+/// Renders `findings` as a SARIF 2.1.0 log with one rule per category. Locations are
+/// relative to `root`, and every finding with a fake value carries a fix replacing
+/// the literal by it.
+///
+/// Columns are counted in UTF-16 code units, as SARIF viewers expect by default, from
+/// the content of the files. Regions of files that cannot be read only give the line.
+pub fn sarif_log(findings: &[Finding], root: &Path) -> Value {
+    let mut contents: HashMap<&PathBuf, Option<String>> = HashMap::new();
+    for finding in findings {
+        contents
+            .entry(&finding.path)
+            .or_insert_with(|| fs::read_to_string(&finding.path).ok());
+    }
+
+    let rule_ids: BTreeSet<String> = findings.iter().map(rule_id).collect();
+    let rules: Vec<Value> = rule_ids
+        .iter()
+        .map(|id| {
+            let category = id.trim_start_matches("pii/").replace('_', " ");
+            json!({
+                "id": id,
+                "shortDescription": { "text": format!("Unmasked {} in a test", category) },
+                "defaultConfiguration": { "level": "error" },
+            })
+        })
+        .collect();
+
+    let results: Vec<Value> = findings
+        .iter()
+        .map(|finding| {
+            let id = rule_id(finding);
+            let rule_index = rule_ids.iter().position(|rule| *rule == id);
+            let artifact = json!({ "uri": uri(&finding.path, root), "uriBaseId": "%SRCROOT%" });
+            let mut result = json!({
+                "ruleId": id,
+                "ruleIndex": rule_index,
+                "level": "error",
+                "message": {
+                    "text": format!(
+                        "{}.{} holds an unmasked {}{}",
+                        finding.struct_name,
+                        finding.field,
+                        finding.category,
+                        if finding.inferred { " (inferred)" } else { "" }
+                    )
+                },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": artifact,
+                        "region": region(finding, contents[&finding.path].as_deref()),
+                    }
+                }],
+            });
+            if let Some(fake) = &finding.fake {
+                result["fixes"] = json!([{
+                    "description": { "text": format!("Replace with the fake value \"{}\"", fake) },
+                    "artifactChanges": [{
+                        "artifactLocation": artifact,
+                        "replacements": [{
+                            "deletedRegion": region(finding, contents[&finding.path].as_deref()),
+                            "insertedContent": { "text": fake },
+                        }],
+                    }],
+                }]);
+            }
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "pii-masker",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "originalUriBaseIds": {
+                "%SRCROOT%": { "uri": root_uri(root) }
+            },
+            "columnKind": "utf16CodeUnits",
+            "results": results,
+        }],
+    })
+}
+
+/// The region of the value of a finding, quotes excluded, in the `content` of its file.
+fn region(finding: &Finding, content: Option<&str>) -> Value {
+    let mut region = json!({
+        "startLine": finding.line,
+        "endLine": finding.line,
+        "byteOffset": finding.range.start,
+        "byteLength": finding.range.len(),
+    });
+    let value = content.and_then(|content| Some((content, content.get(finding.range.clone())?)));
+    if let Some((content, value)) = value {
+        let line_start = content[..finding.range.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let start = content[line_start..finding.range.start]
+            .encode_utf16()
+            .count()
+            + 1;
+        region["startColumn"] = json!(start);
+        region["endColumn"] = json!(start + value.encode_utf16().count());
+    }
+    region
+}
+
+/// The `file` URI of the directory `root`, made absolute so the log can be read from
+/// anywhere.
+fn root_uri(root: &Path) -> String {
+    let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let path = root.to_string_lossy().replace('\\', "/");
+    let path = path.trim_end_matches('/');
+    if path.starts_with('/') {
+        format!("file://{}/", path)
+    } else {
+        // a Windows path such as C:/ws
+        format!("file:///{}/", path)
+    }
+}
+
+/// The path of `path` relative to `root`, with forward slashes.
+fn uri(path: &Path, root: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pii_masker_pii::similarity::Category;
+
+    #[test]
+    fn test_sarif_log() {
+        let root = std::env::temp_dir().join(format!("pii-masker-sarif-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("tests")).unwrap();
+        let line = "    let s = Student { name: \"Zoë\", email: \"jdoe@gmail.com\" };\n";
+        let content = format!("// élève\n{}", line);
+        fs::write(root.join("tests/student.rs"), &content).unwrap();
+        let start = content.find("jdoe").unwrap();
+        let finding = Finding {
+            path: root.join("tests/student.rs"),
+            line: 2,
+            column: line.find("\"jdoe").unwrap() + 1,
+            range: start..start + 14,
+            struct_name: "Student".to_string(),
+            field: "email".to_string(),
+            value: "jdoe@gmail.com".to_string(),
+            category: Category::Email,
+            inferred: false,
+            fake: Some("joe@example.com".to_string()),
+        };
+        let log = sarif_log(&[finding], &root);
+
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "pii/email");
+        assert_eq!(run["columnKind"], "utf16CodeUnits");
+        let base = format!("file://{}/", fs::canonicalize(&root).unwrap().display());
+        assert_eq!(run["originalUriBaseIds"]["%SRCROOT%"]["uri"], base);
+        // a relative root, as when checking the current directory
+        let cwd = fs::canonicalize(".").unwrap();
+        assert_eq!(
+            root_uri(Path::new(".")),
+            format!("file://{}/", cwd.display())
+        );
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], "pii/email");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "tests/student.rs");
+        // "ë" is two bytes but a single UTF-16 code unit
+        assert_eq!(location["region"]["startColumn"], 44);
+        assert_eq!(location["region"]["endColumn"], 58);
+        let replacement = &result["fixes"][0]["artifactChanges"][0]["replacements"][0];
+        assert_eq!(replacement["deletedRegion"]["byteOffset"], start);
+        assert_eq!(replacement["insertedContent"]["text"], "joe@example.com");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use ast_grep_core::Language;
+use pii_masker_pii::similarity::{classify_word, sample_similar_word_for_category, Category};
+use pii_masker_pii::{MResult, MaskerError};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::masker::{MaskContext, PIIMaskable};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaskRequest {
+    source: String,
+    #[serde(default)]
+    fixture: String,
+}
+
+#[derive(Debug, Serialize)]
+struct MaskResponse {
+    source: String,
+    fixture: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClassifyRequest {
+    value: String,
+    #[serde(default = "default_count")]
+    top: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FakeRequest {
+    category: String,
+    #[serde(default = "default_count")]
+    count: usize,
+    /// Pick the fake values most similar to this one rather than random ones.
+    like: Option<String>,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+/// The endpoints of the local masking service, answering with the word pool of a
+/// context generated once at startup. Each request is masked with a fresh pseudonym
+/// table, so the service keeps none of the values sent to it.
+pub struct Service<'a, M, L> {
+    language: L,
+    context: &'a MaskContext<'a>,
+    maskable: PhantomData<M>,
+}
+
+impl<'a, M: PIIMaskable<L>, L: Language> Service<'a, M, L> {
+    pub fn new(language: L, context: &'a MaskContext<'a>) -> Self {
+        Self {
+            language,
+            context,
+            maskable: PhantomData,
+        }
+    }
+
+    /// Answers a request with a status code and a JSON body.
+    ///
+    /// - `POST /mask` takes `{"source", "fixture"}` and returns them masked.
+    /// - `POST /classify` takes `{"value", "top"}` and returns the category of the
+    ///   value, its score for each category and the most similar fake values.
+    /// - `POST /fake` takes `{"category", "count", "like"}` and returns fake values of
+    ///   the category, the most similar to `like` when given.
+    ///
+    /// The query string of `url` is ignored.
+    pub fn handle(&self, method: &str, url: &str, body: &str) -> (u16, Value) {
+        let path = url.split_once('?').map_or(url, |(path, _)| path);
+        let route = match path {
+            "/mask" | "/classify" | "/fake" => path,
+            _ => return (404, json!({ "error": format!("no endpoint at {}", path) })),
+        };
+        if method != "POST" {
+            return (405, json!({ "error": format!("{} expects POST", route) }));
+        }
+        let result = match route {
+            "/mask" => self.mask(body),
+            "/classify" => self.classify(body),
+            _ => self.fake(body),
+        };
+        match result {
+            Ok(value) => (200, value),
+            Err(err) => (400, json!({ "error": format!("{:?}", err) })),
+        }
+    }
+
+    fn mask(&self, body: &str) -> MResult<Value> {
+        let request: MaskRequest = parse(body)?;
+        let context = self.context.fresh();
+        let (source, fixture) = M::mask_tests_with(
+            self.language.clone(),
+            &request.source,
+            &request.fixture,
+            &context,
+        )?;
+        Ok(json!(MaskResponse { source, fixture }))
+    }
+
+    fn classify(&self, body: &str) -> MResult<Value> {
+        let request: ClassifyRequest = parse(body)?;
+        let classification = classify_word(&request.value, self.context.word_pool, request.top)?;
+        // best match first
+        let scores: Vec<Value> = classification
+            .scores
+            .iter()
+            .map(|(category, score)| json!({ "category": category, "score": score }))
+            .collect();
+        Ok(json!({
+            "category": classification.category,
+            "scores": scores,
+            "similar": classification.similar,
+        }))
+    }
+
+    fn fake(&self, body: &str) -> MResult<Value> {
+        let request: FakeRequest = parse(body)?;
+        let category = Category::from_str(&request.category)?;
+        let words: Vec<&String> = self
+            .context
+            .word_pool
+            .iter()
+            .filter(|(pool_category, _)| *pool_category == category)
+            .flat_map(|(_, words)| words)
+            .collect();
+        if words.is_empty() {
+            return Err(MaskerError::SimilarityError(format!(
+                "No fake {} available",
+                category
+            )));
+        }
+        let mut values: Vec<String> = match &request.like {
+            Some(like) => sample_similar_word_for_category(
+                like,
+                category,
+                self.context.word_pool,
+                request.count,
+            )
+            .into_iter()
+            .collect(),
+            None => words
+                .choose_multiple(&mut rand::thread_rng(), request.count)
+                .map(|word| word.to_string())
+                .collect(),
+        };
+        values.sort();
+        Ok(json!({ "category": category, "values": values }))
+    }
+}
+
+/// Serves `service` on `127.0.0.1:port` until the process is stopped.
+pub fn serve<M: PIIMaskable<L>, L: Language>(service: &Service<M, L>, port: u16) -> MResult<()> {
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|err| MaskerError::IoError(format!("listening on port {}: {}", port, err)))?;
+    let content_type = Header::from_bytes("Content-Type", "application/json")
+        .map_err(|_| MaskerError::IoError("invalid header".to_string()))?;
+    for mut request in server.incoming_requests() {
+        let mut body = String::new();
+        let (status, value) = match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => {
+                let method = match request.method() {
+                    Method::Post => "POST",
+                    _ => "",
+                };
+                service.handle(method, request.url(), &body)
+            }
+            Err(err) => (400, json!({ "error": err.to_string() })),
+        };
+        let response = Response::from_string(value.to_string())
+            .with_status_code(status)
+            .with_header(content_type.clone());
+        if let Err(err) = request.respond(response) {
+            eprintln!("Error answering request: {}", err);
+        }
+    }
+    Ok(())
+}
+
+fn parse<'de, T: Deserialize<'de>>(body: &'de str) -> MResult<T> {
+    serde_json::from_str(body).map_err(|err| MaskerError::IoError(format!("invalid body: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust::Rust;
+
+    #[test]
+    fn test_handle() {
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let context = MaskContext::new(&word_pool);
+        let service = Service::<Rust, _>::new(Rust::Rs, &context);
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="email")]
+    email: String,
+}
+
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        email: "jdoe@gmail.com",
+    };
+}
+"#;
+        let body = json!({ "source": source, "fixture": "email: jdoe@gmail.com\n" }).to_string();
+        let (status, masked) = service.handle("POST", "/mask", &body);
+        assert_eq!(status, 200);
+        // the originals are forgotten once the request is answered
+        assert!(context.pseudonyms.fakes().is_empty());
+        assert!(!masked["source"]
+            .as_str()
+            .unwrap()
+            .contains("jdoe@gmail.com"));
+        assert!(!masked["fixture"]
+            .as_str()
+            .unwrap()
+            .contains("jdoe@gmail.com"));
+
+        let (status, classified) =
+            service.handle("POST", "/classify", r#"{"value": "jdoe@gmail.com"}"#);
+        assert_eq!(status, 200);
+        assert_eq!(classified["category"], "email");
+
+        let (status, fakes) = service.handle("POST", "/fake", r#"{"category": "ssn", "count": 3}"#);
+        assert_eq!(status, 200);
+        assert_eq!(fakes["values"].as_array().unwrap().len(), 3);
+
+        assert_eq!(service.handle("GET", "/fake", "").0, 405);
+        assert_eq!(service.handle("POST", "/nope", "").0, 404);
+        let (status, _) = service.handle("POST", "/fake?count=2", r#"{"category": "ssn"}"#);
+        assert_eq!(status, 200);
+        assert_eq!(
+            service.handle("POST", "/fake", r#"{"category": "shoe"}"#).0,
+            400
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use pii_masker_pii::{MResult, MaskerError};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::mapping::{FileKind, Mapping};
+use crate::masker::Replacement;
+use crate::output::write_atomic;
+use crate::workspace::{MaskedFile, WorkspaceMasking};
+
+/// The environment variable holding the passphrase of the vault.
+pub const PASSPHRASE_ENV: &str = "PII_MASKER_VAULT_PASSPHRASE";
+
+/// Leads every vault file, followed by the salt, the nonce and the ciphertext.
+const MAGIC: &[u8] = b"PIIVAULT\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// A change made to a file by masking: the bytes at `range` of the masked file, which
+/// hold `fake`, were `original` before.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub original: String,
+    pub fake: String,
+}
+
+/// A file touched by a masking run, with its replacements and the edits undoing them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultFile {
+    pub path: PathBuf,
+    pub kind: FileKind,
+    pub replacements: Vec<Replacement>,
+    /// Sorted by range, which never overlap.
+    pub edits: Vec<Edit>,
+}
+
+/// The files touched by one masking run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VaultRun {
+    pub files: Vec<VaultFile>,
+}
+
+/// The original and fake values of successive masking runs, kept in a local file
+/// encrypted with XChaCha20-Poly1305 under a key derived from a passphrase with
+/// Argon2id. Runs are restored last first, like an undo stack.
+///
+/// The same file keeps the values replaced by the git filter, file by file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncryptedVault {
+    pub runs: Vec<VaultRun>,
+    #[serde(default)]
+    pub filter: Mapping,
+}
+
+impl From<&WorkspaceMasking> for VaultRun {
+    fn from(masking: &WorkspaceMasking) -> Self {
+        let vault_file = |file: &MaskedFile, kind| VaultFile {
+            path: file.path.clone(),
+            kind,
+            replacements: file.replacements.clone(),
+            edits: edits(file),
+        };
+        let sources = masking
+            .sources
+            .iter()
+            .map(|file| vault_file(file, FileKind::Source));
+        let fixtures = masking
+            .fixtures
+            .iter()
+            .map(|file| vault_file(file, FileKind::Fixture));
+        Self {
+            files: sources.chain(fixtures).collect(),
+        }
+    }
+}
+
+impl EncryptedVault {
+    /// Decrypts the vault at `path`, or returns an empty one when there is no such file.
+    pub fn open(path: &Path, passphrase: &str) -> MResult<Self> {
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(MaskerError::IoError(format!("{}: {}", path.display(), err))),
+        };
+        let invalid = || MaskerError::IoError(format!("{}: not a vault", path.display()));
+        let content = content.strip_prefix(MAGIC).ok_or_else(invalid)?;
+        if content.len() < SALT_LEN + NONCE_LEN {
+            return Err(invalid());
+        }
+        let (salt, content) = content.split_at(SALT_LEN);
+        let (nonce, ciphertext) = content.split_at(NONCE_LEN);
+        let plaintext = cipher(passphrase, salt)?
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                MaskerError::IoError(format!(
+                    "{}: wrong passphrase or corrupted vault",
+                    path.display()
+                ))
+            })?;
+        serde_json::from_slice(&plaintext)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))
+    }
+
+    /// Encrypts the vault to `path` with a fresh salt and nonce.
+    pub fn save(&self, path: &Path, passphrase: &str) -> MResult<()> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let plaintext = serde_json::to_vec(self)
+            .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))?;
+        let ciphertext = cipher(passphrase, &salt)?
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| MaskerError::IoError(format!("{}: encryption failed", path.display())))?;
+
+        let mut content = MAGIC.to_vec();
+        content.extend(salt);
+        content.extend(nonce);
+        content.extend(ciphertext);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|err| MaskerError::IoError(format!("{}: {}", dir.display(), err)))?;
+        }
+        write_atomic(path, content)
+    }
+}
+
+impl VaultFile {
+    /// Puts the original values back into the masked `content` of this file, at the
+    /// ranges of the edits. Fails if any of them no longer holds its fake value, as the
+    /// file changed since it was masked.
+    pub fn restore(&self, content: &str) -> MResult<String> {
+        let mut restored = String::with_capacity(content.len());
+        let mut last = 0;
+        for edit in &self.edits {
+            if edit.range.start < last || content.get(edit.range.clone()) != Some(&edit.fake) {
+                return Err(MaskerError::IoError(format!(
+                    "{}: changed since it was masked (bytes {}..{})",
+                    self.path.display(),
+                    edit.range.start,
+                    edit.range.end
+                )));
+            }
+            restored.push_str(&content[last..edit.range.start]);
+            restored.push_str(&edit.original);
+            last = edit.range.end;
+        }
+        restored.push_str(&content[last..]);
+        Ok(restored)
+    }
+}
+
+/// Lists the edits undoing the masking of `file`, at the ranges its fake values were
+/// written to in its masked content.
+fn edits(file: &MaskedFile) -> Vec<Edit> {
+    let mut edits: Vec<Edit> = file
+        .replacements
+        .iter()
+        .map(|replacement| Edit {
+            range: replacement.fake_range.clone(),
+            original: replacement.original.clone(),
+            fake: replacement.fake.clone(),
+        })
+        .collect();
+    edits.sort_by_key(|edit| edit.range.start);
+    edits
+}
+
+/// Derives the key of the vault from `passphrase` and `salt` with Argon2id.
+fn cipher(passphrase: &str, salt: &[u8]) -> MResult<XChaCha20Poly1305> {
+    if passphrase.is_empty() {
+        return Err(MaskerError::ConfigError(
+            "the vault passphrase is empty".to_string(),
+        ));
+    }
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| MaskerError::ConfigError(format!("deriving the vault key: {}", err)))?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pii_masker_pii::similarity::Category;
+
+    #[test]
+    fn test_vault_restore() {
+        let original = "let s = Student { email: \"jdoe@gmail.com\", name: \"Zoë\" };\nok\n";
+        let masked = "let s = Student { email: \"kris@example.net\", name: \"Ann\" };\nok\n";
+        let replacement = |original_value: &str, fake: &str| {
+            let start = original.find(original_value).unwrap();
+            let fake_start = masked.find(fake).unwrap();
+            Replacement {
+                struct_name: "Student".to_string(),
+                field: "email".to_string(),
+                category: Category::Email,
+                inferred: false,
+                range: start..start + original_value.len(),
+                fake_range: fake_start..fake_start + fake.len(),
+                line: 1,
+                column: start + 1,
+                original: original_value.to_string(),
+                fake: fake.to_string(),
+            }
+        };
+        let replacements = vec![
+            replacement("jdoe@gmail.com", "kris@example.net"),
+            replacement("Zoë", "Ann"),
+        ];
+        let masking = WorkspaceMasking {
+            sources: vec![MaskedFile {
+                path: PathBuf::from("student.rs"),
+                original: original.to_string(),
+                masked: masked.to_string(),
+                replacements: replacements.clone(),
+            }],
+            fixtures: vec![MaskedFile::fixture(
+                Path::new("student.yaml"),
+                "name: Zoë\nnote: Zoëlle\nalias: Zoë\n".to_string(),
+                &replacements[1..],
+            )
+            .unwrap()],
+        };
+        assert_eq!(
+            masking.fixtures[0].masked,
+            "name: Ann\nnote: Zoëlle\nalias: Ann\n"
+        );
+        let run = VaultRun::from(&masking);
+        // the ranges of the replacements, not of a line diff
+        assert_eq!(run.files[0].edits.len(), 2);
+        assert_eq!(run.files[0].edits[0].range, replacements[0].fake_range);
+        // the occurrences replaced in a fixture, not those inside a longer word
+        assert_eq!(run.files[1].edits.len(), 2);
+        let last = masking.fixtures[0].masked.rfind("Ann").unwrap();
+        assert_eq!(run.files[1].edits[1].range, last..last + 3);
+
+        let vault = EncryptedVault {
+            runs: vec![run],
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("pii-masker-vault-{}", std::process::id()));
+        vault.save(&path, "open sesame").unwrap();
+        let content = fs::read(&path).unwrap();
+        assert!(!String::from_utf8_lossy(&content).contains("jdoe"));
+        assert!(EncryptedVault::open(&path, "open barley").is_err());
+
+        let opened = EncryptedVault::open(&path, "open sesame").unwrap();
+        let files = &opened.runs[0].files;
+        assert_eq!(files[0].restore(masked).unwrap(), original);
+        assert!(files[0].restore("let s = Student {};\n").is_err());
+        assert_eq!(
+            files[1].restore(&masking.fixtures[0].masked).unwrap(),
+            masking.fixtures[0].original
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
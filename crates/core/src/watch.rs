@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ast_grep_core::Language;
+use pii_masker_pii::MResult;
+use sha2::{Digest, Sha256};
+
+use crate::masker::{MaskContext, PIIMaskable, Replacement};
+use crate::output::write_atomic;
+use crate::workspace::{read_file, MaskedFile, Workspace, WorkspaceMasking};
+
+/// What a watch remembers between runs.
+#[derive(Debug, Default)]
+pub struct WatchState {
+    /// Digest of the content last written to each file, by canonical path, so the
+    /// notifications of our own writes are recognized.
+    written: HashMap<PathBuf, String>,
+    /// The values masked since the watch started, by source and original value, to be
+    /// applied to the fixtures changed later. A value masked again replaces the earlier
+    /// replacement, and the values of sources no longer in the workspace are dropped.
+    replacements: HashMap<PathBuf, BTreeMap<String, Replacement>>,
+}
+
+impl WatchState {
+    /// Keeps the paths whose content differs from what the watch last wrote to them.
+    pub fn changed_by_others(&self, paths: &BTreeSet<PathBuf>) -> BTreeSet<PathBuf> {
+        paths
+            .iter()
+            .filter(|path| {
+                let Ok(canonical) = fs::canonicalize(path) else {
+                    return false;
+                };
+                match (self.written.get(&canonical), fs::read(path)) {
+                    (Some(digest), Ok(content)) => *digest != hex::encode(Sha256::digest(content)),
+                    (None, Ok(_)) => true,
+                    (_, Err(_)) => false,
+                }
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Masks the sources of `workspace` among the `changed` paths, with the annotations
+    /// of every source, and the fixtures that changed or are fed by a masked source.
+    /// Fixtures receive every value masked since the watch started in the sources
+    /// feeding them.
+    ///
+    /// The fake values given since the watch started are known fakes of the changed
+    /// files in `context`, so editing a file the watch masked does not mask its fakes
+    /// again.
+    pub fn mask_changed<M: PIIMaskable<L>, L: Language + Send + Sync>(
+        &mut self,
+        workspace: &Workspace,
+        changed: &BTreeSet<PathBuf>,
+        language: L,
+        context: &mut MaskContext,
+    ) -> MResult<WorkspaceMasking> {
+        let is_changed = |path: &Path| {
+            fs::canonicalize(path).is_ok_and(|canonical| {
+                changed
+                    .iter()
+                    .any(|changed| fs::canonicalize(changed).is_ok_and(|c| c == canonical))
+            })
+        };
+        let (contents, annotations) = workspace.read_sources::<M, L>(&language)?;
+        self.replacements
+            .retain(|source, _| workspace.sources.contains(source));
+        let mut fakes: HashSet<String> = context.pseudonyms.fakes().into_iter().collect();
+        fakes.extend(
+            self.replacements
+                .values()
+                .flat_map(|replacements| replacements.values())
+                .map(|replacement| replacement.fake.clone()),
+        );
+
+        let mut masking = WorkspaceMasking::default();
+        for (path, content) in contents {
+            if !is_changed(path) {
+                continue;
+            }
+            context
+                .known_fakes
+                .extend(path, fakes.iter().map(String::as_str));
+            let (masked, replacements) = M::mask_source_at(
+                language.clone(),
+                Some(path),
+                &content,
+                &annotations,
+                context,
+            )?;
+            if replacements.is_empty() {
+                continue;
+            }
+            self.replacements.entry(path.clone()).or_default().extend(
+                replacements
+                    .iter()
+                    .map(|replacement| (replacement.original.clone(), replacement.clone())),
+            );
+            masking.sources.push(MaskedFile {
+                path: path.clone(),
+                original: content,
+                masked,
+                replacements,
+            });
+        }
+
+        for path in &workspace.fixtures {
+            let fed = masking
+                .sources
+                .iter()
+                .any(|source| workspace.feeds_fixture(&source.path, path));
+            if !fed && !is_changed(path) {
+                continue;
+            }
+            // a fake already in the fixture is not taken for the value it equals
+            let replacements = self
+                .replacements
+                .iter()
+                .filter(|(source, _)| workspace.feeds_fixture(source, path))
+                .flat_map(|(_, replacements)| replacements.values())
+                .filter(|replacement| !fakes.contains(&replacement.original));
+            if let Some(fixture) = MaskedFile::fixture(path, read_file(path)?, replacements) {
+                masking.fixtures.push(fixture);
+            }
+        }
+
+        Ok(masking)
+    }
+
+    /// Writes the masked files and remembers their content.
+    pub fn write(&mut self, masking: &WorkspaceMasking) -> MResult<()> {
+        for file in masking.files() {
+            write_atomic(&file.path, &file.masked)?;
+            if let Ok(canonical) = fs::canonicalize(&file.path) {
+                self.written
+                    .insert(canonical, hex::encode(Sha256::digest(&file.masked)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust::Rust;
+
+    #[test]
+    fn test_mask_changed() {
+        let root = std::env::temp_dir().join(format!("pii-masker-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("fixtures")).unwrap();
+        let source = r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="email")]
+    email: String,
+}
+
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        email: "jdoe@gmail.com",
+    };
+}
+"#;
+        fs::write(root.join("student.rs"), source).unwrap();
+        fs::write(root.join("other.rs"), source).unwrap();
+        fs::write(
+            root.join("fixtures/student.yaml"),
+            "email: jdoe@gmail.com\n",
+        )
+        .unwrap();
+        let workspace = Workspace::discover(&root, &[], &[], &["fixtures/**".to_string()]).unwrap();
+
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let mut context = MaskContext::new(&word_pool);
+        let mut state = WatchState::default();
+        let changed = BTreeSet::from([root.join("student.rs")]);
+        assert_eq!(state.changed_by_others(&changed), changed);
+        let masking = state
+            .mask_changed::<Rust, _>(&workspace, &changed, Rust::Rs, &mut context)
+            .unwrap();
+        state.write(&masking).unwrap();
+
+        // the unchanged source is left alone, the fixture it shares values with is not
+        assert_eq!(masking.sources.len(), 1);
+        assert_eq!(masking.sources[0].path, root.join("student.rs"));
+        assert_eq!(masking.fixtures.len(), 1);
+        assert!(fs::read_to_string(root.join("other.rs"))
+            .unwrap()
+            .contains("jdoe@gmail.com"));
+
+        // our own writes are not changes
+        let written = BTreeSet::from([root.join("student.rs"), root.join("fixtures/student.yaml")]);
+        assert!(state.changed_by_others(&written).is_empty());
+
+        // a fixture edited later receives the values masked earlier
+        fs::write(
+            root.join("fixtures/student.yaml"),
+            "email: jdoe@gmail.com\n",
+        )
+        .unwrap();
+        let changed = state.changed_by_others(&written);
+        assert_eq!(
+            changed,
+            BTreeSet::from([root.join("fixtures/student.yaml")])
+        );
+        let masking = state
+            .mask_changed::<Rust, _>(&workspace, &changed, Rust::Rs, &mut context)
+            .unwrap();
+        assert!(masking.sources.is_empty());
+        assert!(!masking.fixtures[0].masked.contains("jdoe@gmail.com"));
+
+        // editing a file the watch masked leaves the fakes it wrote alone
+        let masked = fs::read_to_string(root.join("student.rs")).unwrap();
+        fs::write(root.join("student.rs"), format!("{}// edited\n", masked)).unwrap();
+        let changed = BTreeSet::from([root.join("student.rs")]);
+        let masking = state
+            .mask_changed::<Rust, _>(&workspace, &changed, Rust::Rs, &mut context)
+            .unwrap();
+        assert_eq!(masking.files().count(), 0);
+
+        // masking the same value again replaces what was remembered of it
+        fs::write(root.join("student.rs"), source).unwrap();
+        let changed = BTreeSet::from([root.join("student.rs")]);
+        state
+            .mask_changed::<Rust, _>(&workspace, &changed, Rust::Rs, &mut context)
+            .unwrap();
+        assert_eq!(state.replacements.len(), 1);
+        assert_eq!(state.replacements[&root.join("student.rs")].len(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
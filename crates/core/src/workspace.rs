@@ -0,0 +1,1044 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use ast_grep_core::Language;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use pii_masker_pii::similarity::Category;
+use pii_masker_pii::{MResult, MaskerError};
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::cache::{self, Cache, Redact};
+use crate::check::{check_source, Finding};
+use crate::config::FixtureMapping;
+use crate::masker::{mask_fixture, Annotation, MaskContext, PIIMaskable, Replacement};
+
+/// The paths and contents of the sources of a workspace.
+type SourceContents<'a> = Vec<(&'a PathBuf, String)>;
+
+/// The source and fixture files found below a root directory.
+#[derive(Debug)]
+pub struct Workspace {
+    pub root: PathBuf,
+    pub sources: Vec<PathBuf>,
+    pub fixtures: Vec<PathBuf>,
+    /// Errors met reading the annotations of single files, which were skipped.
+    errors: Mutex<Vec<MaskerError>>,
+    /// Source and fixture glob sets restricting which sources' values are applied to
+    /// the fixtures they match.
+    fixture_mappings: Vec<(GlobSet, GlobSet)>,
+    /// The sources referencing each discovered fixture, by normalized path.
+    fixture_sources: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+    /// Sources read for their annotations only, never masked.
+    annotation_sources: Vec<PathBuf>,
+    /// Results of earlier runs, reused for the files that did not change.
+    cache: Option<Cache>,
+}
+
+/// A file whose content changed after masking.
+#[derive(Debug)]
+pub struct MaskedFile {
+    pub path: PathBuf,
+    pub original: String,
+    pub masked: String,
+    pub replacements: Vec<Replacement>,
+}
+
+/// The outcome of masking a whole workspace. Only files that were actually
+/// modified are listed.
+#[derive(Debug, Default)]
+pub struct WorkspaceMasking {
+    pub sources: Vec<MaskedFile>,
+    pub fixtures: Vec<MaskedFile>,
+}
+
+impl WorkspaceMasking {
+    pub fn files(&self) -> impl Iterator<Item = &MaskedFile> {
+        self.sources.iter().chain(self.fixtures.iter())
+    }
+
+    /// The fake values written to each file, to be recorded with `record_fakes`.
+    pub fn fakes(&self) -> impl Iterator<Item = (&Path, &str)> {
+        self.files().flat_map(MaskedFile::fakes)
+    }
+}
+
+impl MaskedFile {
+    /// The fake values written to this file, with its path.
+    pub fn fakes(&self) -> impl Iterator<Item = (&Path, &str)> {
+        self.replacements
+            .iter()
+            .map(|replacement| (self.path.as_path(), replacement.fake.as_str()))
+    }
+
+    /// Replaces the values of `replacements` in the `content` of a fixture, keeping a
+    /// replacement for every occurrence replaced. Returns `None` when the fixture is
+    /// left unchanged.
+    pub(crate) fn fixture<'r>(
+        path: &Path,
+        content: String,
+        replacements: impl IntoIterator<Item = &'r Replacement>,
+    ) -> Option<Self> {
+        let replacements: Vec<Replacement> = replacements.into_iter().cloned().collect();
+        let (masked, replacements) = mask_fixture(&content, &replacements);
+        (!replacements.is_empty()).then(|| Self {
+            path: path.to_path_buf(),
+            original: content,
+            masked,
+            replacements,
+        })
+    }
+}
+
+impl Workspace {
+    /// Walks `root`, honouring `.gitignore` files, and collects the Rust sources and
+    /// the fixtures to mask.
+    ///
+    /// Sources are `.rs` files matching one of `includes` (every `.rs` file when empty),
+    /// fixtures are files matching one of `fixture_globs`. Paths matching one of
+    /// `excludes` are skipped. Globs are matched against paths relative to `root`.
+    pub fn discover(
+        root: &Path,
+        includes: &[String],
+        excludes: &[String],
+        fixture_globs: &[String],
+    ) -> MResult<Self> {
+        let includes = build_glob_set(includes)?;
+        let excludes = build_glob_set(excludes)?;
+        let fixture_globs = build_glob_set(fixture_globs)?;
+
+        let mut sources = vec![];
+        let mut fixtures = vec![];
+        let walker = WalkBuilder::new(root).require_git(false).build();
+        for entry in walker {
+            let entry = entry.map_err(|err| MaskerError::IoError(err.to_string()))?;
+            if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+                continue;
+            }
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            if excludes.is_match(relative) {
+                continue;
+            }
+            if fixture_globs.is_match(relative) {
+                fixtures.push(path.to_path_buf());
+            } else if path.extension().is_some_and(|ext| ext == "rs")
+                && (includes.is_empty() || includes.is_match(relative))
+            {
+                sources.push(path.to_path_buf());
+            }
+        }
+        sources.sort();
+        fixtures.sort();
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            sources,
+            fixtures,
+            errors: Mutex::new(vec![]),
+            fixture_mappings: vec![],
+            fixture_sources: BTreeMap::new(),
+            annotation_sources: vec![],
+            cache: None,
+        })
+    }
+
+    /// Builds a workspace from explicit lists of sources and fixtures.
+    pub fn from_paths(sources: Vec<PathBuf>, fixtures: Vec<PathBuf>) -> Self {
+        Self {
+            root: PathBuf::from("."),
+            sources,
+            fixtures,
+            errors: Mutex::new(vec![]),
+            fixture_mappings: vec![],
+            fixture_sources: BTreeMap::new(),
+            annotation_sources: vec![],
+            cache: None,
+        }
+    }
+
+    /// Reads the annotations of `sources` too, without masking them, e.g. for structs
+    /// declared in a dependency and used in the tests of the workspace.
+    pub fn with_annotation_sources(mut self, sources: Vec<PathBuf>) -> Self {
+        self.annotation_sources = sources;
+        self
+    }
+
+    /// Reuses the results stored in `cache` for the files, annotations, rules and
+    /// context unchanged since they were computed, and stores the others.
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// The cache of the workspace, if any.
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// Restricts the fixtures matched by a mapping to the values masked in the sources
+    /// of that mapping.
+    pub fn with_fixture_mappings(mut self, mappings: &[FixtureMapping]) -> MResult<Self> {
+        for mapping in mappings {
+            self.fixture_mappings.push((
+                build_glob_set(std::slice::from_ref(&mapping.source))?,
+                build_glob_set(&mapping.fixtures)?,
+            ));
+        }
+        Ok(self)
+    }
+
+    /// Adds the fixtures referenced from the tests of every source, e.g. with
+    /// `include_str!` or `fs::read_to_string`. A discovered fixture only receives the
+    /// values masked in the sources referencing it.
+    ///
+    /// `include_str!` paths are resolved against the directory of the source, other
+    /// paths against the crate root the tests run from. Paths that do not exist are
+    /// ignored.
+    pub fn with_discovered_fixtures<M: PIIMaskable<L>, L: Language>(
+        mut self,
+        language: &L,
+    ) -> MResult<Self> {
+        for source in &self.sources {
+            let content = read_file(source)?;
+            let references = M::discover_fixture_references(language, &content).map_err(|err| {
+                MaskerError::RuleParseError(format!("{}: {:?}", source.display(), err))
+            })?;
+            let source_dir = source.parent().unwrap_or(Path::new("."));
+            for reference in references {
+                let base = if reference.relative_to_source {
+                    source_dir.to_path_buf()
+                } else {
+                    find_crate_root(source_dir).unwrap_or_else(|| source_dir.to_path_buf())
+                };
+                let fixture = normalize(&base.join(&reference.path));
+                if !fixture.is_file() || self.sources.iter().any(|path| normalize(path) == fixture)
+                {
+                    continue;
+                }
+                if !self.fixtures.iter().any(|path| normalize(path) == fixture) {
+                    self.fixtures.push(fixture.clone());
+                }
+                self.fixture_sources
+                    .entry(fixture)
+                    .or_default()
+                    .insert(normalize(source));
+            }
+        }
+        self.fixtures.sort();
+        Ok(self)
+    }
+
+    /// Finds the root of the Cargo workspace containing `start`: the outermost
+    /// directory whose `Cargo.toml` declares a `[workspace]`, or else the nearest
+    /// directory with a `Cargo.toml`.
+    pub fn find_cargo_root(start: &Path) -> Option<PathBuf> {
+        let mut nearest = None;
+        let mut workspace = None;
+        for dir in start.ancestors() {
+            let manifest = dir.join("Cargo.toml");
+            if let Ok(content) = fs::read_to_string(&manifest) {
+                if nearest.is_none() {
+                    nearest = Some(dir.to_path_buf());
+                }
+                if content.contains("[workspace]") {
+                    workspace = Some(dir.to_path_buf());
+                }
+            }
+        }
+        workspace.or(nearest)
+    }
+
+    /// Masks every source of the workspace and propagates the replacements to all
+    /// fixtures.
+    ///
+    /// Annotations are collected from all sources first, so a struct declared in one
+    /// file is masked in the tests of any other file.
+    ///
+    /// Files are processed concurrently, and listed in the order of the workspace
+    /// whatever the scheduling.
+    pub fn mask<M: PIIMaskable<L>, L: Language + Send + Sync>(
+        &self,
+        language: L,
+        context: &MaskContext,
+    ) -> MResult<WorkspaceMasking> {
+        let (contents, annotations) = self.read_sources::<M, L>(&language)?;
+        let shared = self.shared_digest::<M, L>(&language, &annotations, context);
+        self.assign_fakes::<M, L>(&language, &contents, &annotations, context)?;
+
+        let keys: Vec<Vec<&str>> = contents
+            .iter()
+            .map(|(_, content)| vec!["mask", &shared, content])
+            .collect();
+        let masked: Vec<(String, Vec<Replacement>)> = self.cached_each(
+            &keys,
+            &contents,
+            context,
+            |(_, replacements): &(String, Vec<Replacement>)| {
+                replacements
+                    .iter()
+                    .map(|replacement| {
+                        let original = replacement.original.clone();
+                        (replacement.category, original, replacement.fake.clone())
+                    })
+                    .collect()
+            },
+            |i| {
+                let (path, content) = &contents[i];
+                M::mask_source_at(language.clone(), Some(path), content, &annotations, context)
+            },
+        )?;
+        let sources = contents
+            .into_iter()
+            .zip(masked)
+            .filter(|(_, (_, replacements))| !replacements.is_empty())
+            .map(|((path, content), (masked, replacements))| MaskedFile {
+                path: path.clone(),
+                original: content,
+                masked,
+                replacements,
+            });
+        let mut masking = WorkspaceMasking {
+            sources: sources.collect(),
+            fixtures: vec![],
+        };
+
+        let fixtures = self
+            .fixtures
+            .par_iter()
+            .map(|path| {
+                let content = read_file(path)?;
+                let sources = masking
+                    .sources
+                    .iter()
+                    .filter(|source| self.feeds_fixture(&source.path, path));
+                let replacements: Vec<&Replacement> =
+                    sources.flat_map(|source| &source.replacements).collect();
+                if self.cache.is_none() {
+                    return Ok(MaskedFile::fixture(path, content, replacements));
+                }
+                let replacements_json = serde_json::to_string(&replacements).unwrap_or_default();
+                let inputs = ["fixture", &content, &replacements_json];
+                let masked = self.cached(&inputs, &content, || {
+                    let fixture = MaskedFile::fixture(path, content.clone(), replacements);
+                    Ok(fixture.map(|fixture| (fixture.masked, fixture.replacements)))
+                })?;
+                Ok(masked.map(|(masked, replacements)| MaskedFile {
+                    path: path.clone(),
+                    original: content,
+                    masked,
+                    replacements,
+                }))
+            })
+            .collect::<MResult<Vec<_>>>()?;
+        masking.fixtures = fixtures.into_iter().flatten().collect();
+
+        Ok(masking)
+    }
+
+    /// Takes the errors met reading single files since the last call, in no
+    /// particular order. The files were skipped, the others processed.
+    pub fn take_errors(&self) -> Vec<MaskerError> {
+        std::mem::take(&mut *self.errors.lock().unwrap())
+    }
+
+    /// Records the error of `result`, if any, for `take_errors`.
+    fn skip_error<T>(&self, result: MResult<T>) -> Option<T> {
+        result
+            .map_err(|err| self.errors.lock().unwrap().push(err))
+            .ok()
+    }
+
+    /// Reports the annotated fields of every test in the workspace that still hold a
+    /// value looking like real PII. Nothing is modified.
+    pub fn check<M: PIIMaskable<L>, L: Language + Send + Sync>(
+        &self,
+        language: L,
+        context: &MaskContext,
+    ) -> MResult<Vec<Finding>> {
+        let (contents, annotations) = self.read_sources::<M, L>(&language)?;
+        let shared = self.shared_digest::<M, L>(&language, &annotations, context);
+        self.assign_fakes::<M, L>(&language, &contents, &annotations, context)?;
+        let paths: Vec<String> = contents
+            .iter()
+            .map(|(path, _)| path.to_string_lossy().to_string())
+            .collect();
+        let keys: Vec<Vec<&str>> = contents
+            .iter()
+            .zip(&paths)
+            .map(|((_, content), path)| vec!["check", &shared, path, content])
+            .collect();
+        let findings: Vec<Vec<Finding>> = self.cached_each(
+            &keys,
+            &contents,
+            context,
+            |findings: &Vec<Finding>| {
+                findings
+                    .iter()
+                    .filter_map(|finding| {
+                        let fake = finding.fake.clone()?;
+                        Some((finding.category, finding.value.clone(), fake))
+                    })
+                    .collect()
+            },
+            |i| {
+                let (path, content) = &contents[i];
+                check_source::<M, L>(language.clone(), path, content, &annotations, context)
+            },
+        )?;
+        Ok(findings.into_iter().flatten().collect())
+    }
+
+    /// Lists the struct annotations declared in each source, skipping sources that
+    /// declare none. Errors reading a single file are kept for `take_errors`.
+    pub fn inventory<M: PIIMaskable<L>, L: Language + Send + Sync>(
+        &self,
+        language: &L,
+    ) -> MResult<Vec<(PathBuf, Vec<Annotation>)>> {
+        let inventory: Vec<_> = self
+            .sources
+            .par_iter()
+            .filter_map(|path| {
+                let content = self.skip_error(read_file(path))?;
+                let annotations =
+                    self.skip_error(self.annotations::<M, L>(language, path, &content))?;
+                Some((path.clone(), annotations))
+            })
+            .collect();
+        Ok(inventory
+            .into_iter()
+            .filter(|(_, annotations)| !annotations.is_empty())
+            .collect())
+    }
+
+    /// Tells whether the values masked in `source` are applied to `fixture`: always,
+    /// unless the fixture was discovered from other sources or is matched by mappings
+    /// none of which matches the source.
+    pub(crate) fn feeds_fixture(&self, source: &Path, fixture: &Path) -> bool {
+        let referencing = self.fixture_sources.get(&normalize(fixture));
+        if referencing.is_some_and(|sources| sources.contains(&normalize(source))) {
+            return true;
+        }
+        let source = source.strip_prefix(&self.root).unwrap_or(source);
+        let fixture = fixture.strip_prefix(&self.root).unwrap_or(fixture);
+        let mut mappings = self
+            .fixture_mappings
+            .iter()
+            .filter(|(_, fixtures)| fixtures.is_match(fixture))
+            .peekable();
+        if mappings.peek().is_none() {
+            return referencing.is_none();
+        }
+        mappings.any(|(sources, _)| sources.is_match(source))
+    }
+
+    /// Reads every source and collects the struct annotations declared across all of
+    /// them. Errors reading a single file are kept for `take_errors`: a source that
+    /// cannot be read is skipped, one whose annotations cannot be read is still listed.
+    pub(crate) fn read_sources<M: PIIMaskable<L>, L: Language + Send + Sync>(
+        &self,
+        language: &L,
+    ) -> MResult<(SourceContents<'_>, Vec<Annotation>)> {
+        let read = |path| -> Option<(String, Vec<Annotation>)> {
+            let content = self.skip_error(read_file(path))?;
+            // a source whose annotations cannot be read is still masked with the others'
+            let annotations = self
+                .skip_error(self.annotations::<M, L>(language, path, &content))
+                .unwrap_or_default();
+            Some((content, annotations))
+        };
+        let sources: Vec<_> = self.sources.par_iter().map(|path| read(path)).collect();
+        let annotation_sources: Vec<_> = self
+            .annotation_sources
+            .par_iter()
+            .map(|path| read(path))
+            .collect();
+
+        let mut contents = vec![];
+        let mut annotations = vec![];
+        for (path, source) in self.sources.iter().zip(sources) {
+            if let Some((content, file_annotations)) = source {
+                annotations.extend(file_annotations);
+                contents.push((path, content));
+            }
+        }
+        for (_, file_annotations) in annotation_sources.into_iter().flatten() {
+            annotations.extend(file_annotations);
+        }
+        Ok((contents, annotations))
+    }
+
+    /// Discovers the struct annotations declared in `content`, or reuses those found
+    /// in the same content earlier.
+    fn annotations<M: PIIMaskable<L>, L: Language>(
+        &self,
+        language: &L,
+        path: &Path,
+        content: &str,
+    ) -> MResult<Vec<Annotation>> {
+        let Some(cache) = &self.cache else {
+            return discover_annotations::<M, L>(language, path, content);
+        };
+        let rules = cache::rule_set_digest::<M, L>(language.clone());
+        let key = cache.key(&["annotations", &rules, content]);
+        cache.get_or_insert_with(&key, || {
+            discover_annotations::<M, L>(language, path, content)
+        })
+    }
+
+    /// Digest of the inputs the result of every file depends on besides its content:
+    /// the rules, the annotations of the whole workspace and the context. Empty when
+    /// there is no cache.
+    fn shared_digest<M: PIIMaskable<L>, L: Language>(
+        &self,
+        language: &L,
+        annotations: &[Annotation],
+        context: &MaskContext,
+    ) -> String {
+        if self.cache.is_none() {
+            return String::new();
+        }
+        let annotations = serde_json::to_string(annotations).unwrap_or_default();
+        cache::digest(&[
+            &cache::rule_set_digest::<M, L>(language.clone()),
+            &annotations,
+            &cache::context_digest(context),
+        ])
+    }
+
+    /// With a key, gives their fake values to the values masked in `contents` up front,
+    /// in a fixed order, so they do not depend on the order files are masked in.
+    fn assign_fakes<M: PIIMaskable<L>, L: Language + Send + Sync>(
+        &self,
+        language: &L,
+        contents: &SourceContents,
+        annotations: &Vec<Annotation>,
+        context: &MaskContext,
+    ) -> MResult<()> {
+        if context.key.is_none() {
+            return Ok(());
+        }
+        let values = contents
+            .par_iter()
+            .map(|(path, content)| {
+                M::masked_values(language.clone(), path, content, annotations, context)
+            })
+            .collect::<MResult<Vec<_>>>()?;
+        context.assign_fakes(values.into_iter().flatten().collect());
+        Ok(())
+    }
+
+    /// Computes `compute(i)` for every file concurrently, reusing the result stored for
+    /// the inputs `keys[i]` when the workspace has a cache.
+    ///
+    /// Stored results are taken first, in order, and the fake values `fakes` lists in
+    /// them are recorded in the pseudonyms of `context`, so the results computed next
+    /// give the same fakes to the same values. A stored result disagreeing with the
+    /// fakes recorded before it, as when it comes from a run with another pool, is
+    /// computed again.
+    fn cached_each<T: Serialize + DeserializeOwned + Redact + Clone + Send>(
+        &self,
+        keys: &[Vec<&str>],
+        contents: &SourceContents,
+        context: &MaskContext,
+        fakes: impl Fn(&T) -> Vec<(Category, String, String)>,
+        compute: impl Fn(usize) -> MResult<T> + Sync,
+    ) -> MResult<Vec<T>> {
+        let Some(cache) = &self.cache else {
+            return (0..keys.len()).into_par_iter().map(&compute).collect();
+        };
+        let keys: Vec<String> = keys.iter().map(|inputs| cache.key(inputs)).collect();
+        let stored: Vec<Option<T>> = keys
+            .iter()
+            .zip(contents)
+            .map(|(key, (_, content))| {
+                cache.get_redacted(key, content, |value| {
+                    context.pseudonyms.record(fakes(value))
+                })
+            })
+            .collect();
+        stored
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, stored)| match stored {
+                Some(value) => Ok(value),
+                None => {
+                    let value = compute(i)?;
+                    cache.put_redacted(&keys[i], &value);
+                    Ok(value)
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the result computed from `content` stored for `inputs`, or computes it
+    /// and stores it when the workspace has a cache.
+    fn cached<T: Serialize + DeserializeOwned + Redact + Clone>(
+        &self,
+        inputs: &[&str],
+        content: &str,
+        compute: impl FnOnce() -> MResult<T>,
+    ) -> MResult<T> {
+        match &self.cache {
+            Some(cache) => cache.get_or_insert_redacted(&cache.key(inputs), content, compute),
+            None => compute(),
+        }
+    }
+}
+
+fn discover_annotations<M: PIIMaskable<L>, L: Language>(
+    language: &L,
+    path: &Path,
+    content: &str,
+) -> MResult<Vec<Annotation>> {
+    M::discover_struct_annotations(language, content)
+        .map_err(|err| MaskerError::RuleParseError(format!("{}: {:?}", path.display(), err)))
+}
+
+/// Finds the nearest directory at or above `start` holding a `Cargo.toml`.
+fn find_crate_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join("Cargo.toml").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// Removes the `.` components of `path` and folds its `..` components, without
+/// touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn build_glob_set(globs: &[String]) -> MResult<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        let glob = Glob::new(glob).map_err(|err| MaskerError::GlobError(err.to_string()))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|err| MaskerError::GlobError(err.to_string()))
+}
+
+pub(crate) fn read_file(path: &Path) -> MResult<String> {
+    fs::read_to_string(path)
+        .map_err(|err| MaskerError::IoError(format!("{}: {}", path.display(), err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust::Rust;
+
+    #[test]
+    fn test_mask_workspace() {
+        let root = std::env::temp_dir().join(format!("pii-masker-ws-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("tests")).unwrap();
+        fs::create_dir_all(root.join("fixtures")).unwrap();
+        fs::create_dir_all(root.join("ignored")).unwrap();
+        fs::write(root.join(".gitignore"), "ignored/\n").unwrap();
+        fs::write(
+            root.join("src/student.rs"),
+            r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="first_name")]
+    first_name: String,
+}
+"#,
+        )
+        .unwrap();
+        let test_source = r#"
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        first_name: "Qwzyx",
+    };
+}
+"#;
+        fs::write(root.join("tests/student.rs"), test_source).unwrap();
+        fs::write(root.join("ignored/student.rs"), test_source).unwrap();
+        fs::write(root.join("fixtures/student.yaml"), "first_name: Qwzyx\n").unwrap();
+
+        let workspace = Workspace::discover(&root, &[], &[], &["fixtures/**".to_string()]).unwrap();
+        assert_eq!(workspace.sources.len(), 2);
+        assert_eq!(workspace.fixtures.len(), 1);
+
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let context = MaskContext::new(&word_pool);
+        let masking = workspace.mask::<Rust, _>(Rust::Rs, &context).unwrap();
+        assert_eq!(masking.sources.len(), 1);
+        assert_eq!(masking.sources[0].path, root.join("tests/student.rs"));
+        assert!(!masking.sources[0].masked.contains("Qwzyx"));
+        assert_eq!(masking.fixtures.len(), 1);
+        assert!(!masking.fixtures[0].masked.contains("Qwzyx"));
+        assert_eq!(masking.fixtures[0].replacements.len(), 1);
+
+        let mapping = FixtureMapping {
+            source: "src/**".to_string(),
+            fixtures: vec!["fixtures/*.yaml".to_string()],
+        };
+        let workspace = workspace.with_fixture_mappings(&[mapping]).unwrap();
+        let masking = workspace.mask::<Rust, _>(Rust::Rs, &context).unwrap();
+        assert_eq!(masking.sources.len(), 1);
+        assert!(masking.fixtures.is_empty());
+
+        // a source whose annotations cannot be parsed is reported, the others masked
+        fs::write(
+            root.join("src/broken.rs"),
+            "#[derive(PIIMask)]\nstruct Broken {\n    #[pii_mask(first_name)]\n    first_name: String,\n}\n",
+        )
+        .unwrap();
+        let workspace = Workspace::discover(&root, &[], &[], &["fixtures/**".to_string()]).unwrap();
+        let masking = workspace.mask::<Rust, _>(Rust::Rs, &context).unwrap();
+        assert_eq!(masking.sources.len(), 1);
+        let errors = workspace.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert!(format!("{:?}", errors[0]).contains("broken.rs"));
+        assert!(workspace.take_errors().is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_mask_fixture_tokens() {
+        let replacement = |original: &str, fake: &str| Replacement {
+            struct_name: "Student".to_string(),
+            field: "first_name".to_string(),
+            category: Category::FirstName,
+            inferred: false,
+            range: 0..0,
+            fake_range: 0..0,
+            line: 1,
+            column: 1,
+            original: original.to_string(),
+            fake: fake.to_string(),
+        };
+        let replacements = [
+            replacement("John", "Joan"),
+            replacement("j.doe@gmail.com", "k.roe@mail.com"),
+        ];
+        let content = "name: John\nfriend: Johnny\nemail: jXdoe@gmail.com\nalias: John\nemail: j.doe@gmail.com\n";
+        let file = MaskedFile::fixture(
+            Path::new("fixture.yaml"),
+            content.to_string(),
+            &replacements,
+        )
+        .unwrap();
+        assert_eq!(
+            file.masked,
+            "name: Joan\nfriend: Johnny\nemail: jXdoe@gmail.com\nalias: Joan\nemail: k.roe@mail.com\n"
+        );
+        assert_eq!(file.replacements.len(), 3);
+        for replacement in &file.replacements {
+            assert_eq!(&content[replacement.range.clone()], replacement.original);
+            assert_eq!(
+                &file.masked[replacement.fake_range.clone()],
+                replacement.fake
+            );
+        }
+        assert_eq!(
+            (file.replacements[1].line, file.replacements[1].column),
+            (4, 8)
+        );
+    }
+
+    #[test]
+    fn test_check_after_mask() {
+        let root = std::env::temp_dir().join(format!("pii-masker-remask-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("tests")).unwrap();
+        fs::write(root.join(crate::config::CONFIG_FILE_NAME), "").unwrap();
+        fs::write(
+            root.join("tests/student.rs"),
+            r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="first_name")]
+    first_name: String,
+    #[pii_mask(faker="last_name")]
+    last_name: String,
+    #[pii_mask(faker="ssn")]
+    ssn: String,
+    #[pii_mask(faker="inferred")]
+    mobile: String
+}
+
+#[test]
+fn test_lookup_student() {
+    let expected_student = Student {
+        first_name: "John",
+        last_name: "Doe",
+        ssn: "123-45-6789",
+        mobile: "310-444-2211"
+    };
+}
+"#,
+        )
+        .unwrap();
+        let config =
+            crate::config::Config::load(&root.join(crate::config::CONFIG_FILE_NAME)).unwrap();
+        let workspace = Workspace::discover(&root, &[], &[], &[]).unwrap();
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let mut context = MaskContext::new(&word_pool);
+        context.allowlist = config.allowlist().unwrap();
+        assert_eq!(
+            workspace
+                .check::<Rust, _>(Rust::Rs, &context)
+                .unwrap()
+                .len(),
+            4
+        );
+
+        let masking = workspace.mask::<Rust, _>(Rust::Rs, &context).unwrap();
+        for file in masking.files() {
+            fs::write(&file.path, &file.masked).unwrap();
+        }
+        crate::config::record_fakes(&config.fakes_path(&root), masking.fakes()).unwrap();
+
+        // the values written by the masker are not reported, nor masked again
+        context.known_fakes = config.known_fakes(&root).unwrap();
+        assert!(workspace
+            .check::<Rust, _>(Rust::Rs, &context)
+            .unwrap()
+            .is_empty());
+        let masking = workspace.mask::<Rust, _>(Rust::Rs, &context).unwrap();
+        assert_eq!(masking.files().count(), 0);
+
+        // in another file, a value equal to one of those fakes is still reported
+        let masked = fs::read_to_string(root.join("tests/student.rs")).unwrap();
+        let other = masked.replace("test_lookup_student", "test_other_student");
+        fs::write(root.join("tests/other.rs"), other).unwrap();
+        let workspace = Workspace::discover(&root, &[], &[], &[]).unwrap();
+        let findings = workspace.check::<Rust, _>(Rust::Rs, &context).unwrap();
+        assert!(!findings.is_empty());
+        assert!(findings
+            .iter()
+            .all(|finding| finding.path == root.join("tests/other.rs")));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_discover_fixtures() {
+        let root = std::env::temp_dir().join(format!("pii-masker-refs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("tests/fixtures")).unwrap();
+        fs::write(root.join("Cargo.toml"), "[package]\nname = \"students\"\n").unwrap();
+        fs::write(
+            root.join("tests/student.rs"),
+            r#"
+#[derive(Debug,PIIMask)]
+struct Student {
+    #[pii_mask(faker="first_name")]
+    first_name: String,
+}
+
+#[test]
+fn test_lookup_student() {
+    let fixture = include_str!("fixtures/student.yaml");
+    let other = std::fs::read_to_string("tests/fixtures/student.json").unwrap();
+    let missing = std::fs::read_to_string("tests/fixtures/missing.json").unwrap();
+    let expected_student = Student {
+        first_name: "Qwzyx",
+    };
+}
+"#,
+        )
+        .unwrap();
+        fs::write(
+            root.join("tests/fixtures/student.yaml"),
+            "first_name: Qwzyx\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("tests/fixtures/student.json"),
+            "{\"first_name\": \"Qwzyx\"}",
+        )
+        .unwrap();
+
+        let workspace = Workspace::discover(&root, &[], &[], &[])
+            .unwrap()
+            .with_discovered_fixtures::<Rust, _>(&Rust::Rs)
+            .unwrap();
+        assert_eq!(
+            workspace.fixtures,
+            vec![
+                root.join("tests/fixtures/student.json"),
+                root.join("tests/fixtures/student.yaml")
+            ]
+        );
+
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let masking = workspace
+            .mask::<Rust, _>(Rust::Rs, &MaskContext::new(&word_pool))
+            .unwrap();
+        assert_eq!(masking.fixtures.len(), 2);
+        assert!(masking
+            .fixtures
+            .iter()
+            .all(|fixture| !fixture.masked.contains("Qwzyx")));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_mask_workspace_concurrently() {
+        let root = std::env::temp_dir().join(format!("pii-masker-par-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("tests")).unwrap();
+        fs::create_dir_all(root.join("fixtures")).unwrap();
+        for i in 0..24 {
+            fs::write(
+                root.join(format!("tests/student_{:02}.rs", i)),
+                format!(
+                    r#"
+#[derive(Debug,PIIMask)]
+struct Student{i} {{
+    #[pii_mask(faker="email")]
+    email: String,
+}}
+
+#[test]
+fn test_lookup_student() {{
+    let expected_student = Student{i} {{
+        email: "student{i}@gmail.com",
+    }};
+}}
+"#
+                ),
+            )
+            .unwrap();
+            fs::write(
+                root.join(format!("fixtures/student_{:02}.yaml", i)),
+                format!("email: student{}@gmail.com\n", i),
+            )
+            .unwrap();
+        }
+        let workspace = Workspace::discover(&root, &[], &[], &["fixtures/**".to_string()]).unwrap();
+
+        let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+        let context = MaskContext::new(&word_pool);
+        let summary = |masking: WorkspaceMasking| -> Vec<(PathBuf, String)> {
+            masking
+                .files()
+                .map(|file| (file.path.clone(), file.masked.clone()))
+                .collect()
+        };
+        let sequential = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(|| workspace.mask::<Rust, _>(Rust::Rs, &context).unwrap());
+        let concurrent = rayon::ThreadPoolBuilder::new()
+            .num_threads(8)
+            .build()
+            .unwrap()
+            .install(|| workspace.mask::<Rust, _>(Rust::Rs, &context).unwrap());
+
+        let sequential = summary(sequential);
+        assert_eq!(sequential.len(), 48);
+        // sources first, then fixtures, each in path order
+        assert!(sequential[..24]
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+        assert!(sequential[24..]
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(sequential, summary(concurrent));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_mask_workspace_cached() {
+        let root = std::env::temp_dir().join(format!("pii-masker-cached-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("tests")).unwrap();
+        let student = |email: &str| {
+            format!(
+                r#"
+#[derive(Debug,PIIMask)]
+struct Student {{
+    #[pii_mask(faker="email")]
+    email: String,
+}}
+
+#[test]
+fn test_lookup_student() {{
+    let expected_student = Student {{
+        email: "{email}",
+    }};
+}}
+"#
+            )
+        };
+        fs::write(root.join("tests/a.rs"), student("jdoe@gmail.com")).unwrap();
+        fs::write(root.join("tests/b.rs"), student("asmith@yahoo.com")).unwrap();
+        fs::write(root.join("student.yaml"), "email: jdoe@gmail.com\n").unwrap();
+
+        // every run generates its own word pool, as without a seed
+        let mask = || {
+            let word_pool = pii_masker_pii::similarity::generate_fake_words_pool(100);
+            let context = MaskContext::new(&word_pool);
+            let workspace = Workspace::discover(&root, &[], &[], &["*.yaml".to_string()])
+                .unwrap()
+                .with_cache(Cache::open(&root, &[]));
+            let masking = workspace.mask::<Rust, _>(Rust::Rs, &context).unwrap();
+            let masked: Vec<(PathBuf, String, usize)> = masking
+                .files()
+                .map(|file| {
+                    (
+                        file.path.clone(),
+                        file.masked.clone(),
+                        file.replacements.len(),
+                    )
+                })
+                .collect();
+            (masked, workspace.cache().unwrap().stats())
+        };
+
+        let (first, (hits, _)) = mask();
+        assert_eq!(hits, 0);
+        assert_eq!(first.len(), 3);
+        // 2 annotation sets, 2 sources and 1 fixture, with their replacements
+        let (second, stats) = mask();
+        assert_eq!(stats, (5, 0));
+        assert_eq!(second, first);
+
+        fs::write(
+            root.join("tests/b.rs"),
+            format!("// b\n{}", student("jdoe@gmail.com")),
+        )
+        .unwrap();
+        let (third, (_, misses)) = mask();
+        // the annotations and masking of the changed source, and the fixture it feeds
+        assert_eq!(misses, 3);
+        assert_eq!(third[0], first[0]);
+        // the value masked in the cached source keeps its fake in the changed one
+        assert_eq!(third[1].1, format!("// b\n{}", first[0].1));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::OnceLock;
+
+use rand::Rng;
+use regex::Regex;
+
+use crate::similarity::Category;
+
+/// How many candidates are drawn before giving up on a value.
+const ATTEMPTS: usize = 32;
+
+/// Tells whether values of `category` are structured identifiers, whose shape matters
+/// more than their resemblance to real words.
+pub fn is_structured(category: Category) -> bool {
+    matches!(
+        category,
+        Category::Email
+            | Category::Ssn
+            | Category::PhoneNumber
+            | Category::CreditCard
+            | Category::ZipCode
+            | Category::PositiveDecimal
+    )
+}
+
+/// Generates a fake value shaped like `original`: digits stay digits, letters stay
+/// letters of the same case, and every other character, such as `-`, `.`, `@` or a
+/// space, stays where it was. The top-level domain of an email is kept.
+///
+/// Candidates must also pass the checks of `category`: SSNs that could be issued,
+/// syntactically valid emails and card numbers with a valid check digit. Returns `None`
+/// when no candidate differing from `original` passes them, e.g. when `original`
+/// itself is malformed.
+pub fn format_preserving_fake<R: Rng + ?Sized>(
+    original: &str,
+    category: Category,
+    rng: &mut R,
+) -> Option<String> {
+    let kept = match category {
+        Category::Email => original
+            .rfind('.')
+            .filter(|dot| original[..*dot].contains('@')),
+        _ => None,
+    }
+    .unwrap_or(original.len());
+
+    for _ in 0..ATTEMPTS {
+        let mut fake: String = original[..kept]
+            .chars()
+            .map(|c| randomize(c, rng))
+            .collect();
+        fake.push_str(&original[kept..]);
+        if category == Category::CreditCard {
+            fake = with_check_digit(&fake);
+        }
+        if fake != original && is_valid(&fake, category) {
+            return Some(fake);
+        }
+    }
+    None
+}
+
+/// Tells whether `digits` pass the Luhn check of card numbers.
+pub fn luhn_valid(digits: &str) -> bool {
+    luhn_sum(digits).is_multiple_of(10)
+}
+
+fn luhn_sum(digits: &str) -> u32 {
+    digits
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| match i % 2 {
+            0 => d,
+            _ if d * 2 > 9 => d * 2 - 9,
+            _ => d * 2,
+        })
+        .sum()
+}
+
+fn randomize<R: Rng + ?Sized>(c: char, rng: &mut R) -> char {
+    if c.is_ascii_digit() {
+        rng.gen_range('0'..='9')
+    } else if c.is_uppercase() {
+        rng.gen_range('A'..='Z')
+    } else if c.is_alphabetic() {
+        rng.gen_range('a'..='z')
+    } else {
+        c
+    }
+}
+
+/// Replaces the last digit of `value` so its digits pass the Luhn check.
+fn with_check_digit(value: &str) -> String {
+    let Some(last) = value.rfind(|c: char| c.is_ascii_digit()) else {
+        return value.to_string();
+    };
+    let digits: String = value[..last].chars().filter(char::is_ascii_digit).collect();
+    // the check digit is not doubled, so it only has to complete the sum
+    let sum = luhn_sum(&format!("{}0", digits));
+    let check = (10 - sum % 10) % 10;
+    format!("{}{}{}", &value[..last], check, &value[last + 1..])
+}
+
+pub(crate) fn is_valid(value: &str, category: Category) -> bool {
+    let digits: String = value.chars().filter(char::is_ascii_digit).collect();
+    match category {
+        Category::Ssn if digits.len() == 9 => {
+            let (area, rest) = digits.split_at(3);
+            let (group, serial) = rest.split_at(2);
+            area != "000"
+                && area != "666"
+                && !area.starts_with('9')
+                && group != "00"
+                && serial != "0000"
+        }
+        Category::Email => email_regex().is_match(value) && !value.contains(".."),
+        Category::CreditCard => luhn_valid(&digits),
+        _ => true,
+    }
+}
+
+fn email_regex() -> &'static Regex {
+    static EMAIL: OnceLock<Regex> = OnceLock::new();
+    EMAIL.get_or_init(|| {
+        Regex::new(
+            r"^[A-Za-z0-9](?:[A-Za-z0-9._%+-]*[A-Za-z0-9])?@(?:[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?\.)+[A-Za-z]{2,}$",
+        )
+        .unwrap()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_format_preserving_fake() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let class = |c: char| match c {
+            '0'..='9' => 'd',
+            'a'..='z' => 'l',
+            'A'..='Z' => 'u',
+            other => other,
+        };
+        let shape = |value: &str| value.chars().map(class).collect::<String>();
+
+        for _ in 0..100 {
+            let ssn = format_preserving_fake("123-45-6789", Category::Ssn, &mut rng).unwrap();
+            assert_eq!(shape(&ssn), "ddd-dd-dddd");
+            assert!(!ssn.starts_with("000") && !ssn.starts_with("666"));
+
+            let email =
+                format_preserving_fake("John.Doe@gmail.com", Category::Email, &mut rng).unwrap();
+            assert_eq!(shape(&email), "ulll.ull@lllll.lll");
+            assert!(email.ends_with(".com"));
+            assert!(email_regex().is_match(&email));
+
+            let card =
+                format_preserving_fake("4111 1111 1111 1111", Category::CreditCard, &mut rng)
+                    .unwrap();
+            assert_eq!(shape(&card), "dddd dddd dddd dddd");
+            assert!(luhn_valid(&card.replace(' ', "")));
+        }
+
+        // a malformed email cannot be turned into a valid one
+        assert_eq!(
+            format_preserving_fake("not an email", Category::Email, &mut rng),
+            None
+        );
+        assert!(is_structured(Category::Ssn));
+        assert!(!is_structured(Category::FirstName));
+    }
+}
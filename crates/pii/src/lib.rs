@@ -1,9 +1,15 @@
+pub mod format;
+
 pub mod similarity;
 
 #[derive(Debug)]
 pub enum MaskerError {
     RuleParseError(String),
     SimilarityError(String),
+    IoError(String),
+    GlobError(String),
+    GitError(String),
+    ConfigError(String),
 }
 
 pub type MResult<T> = std::result::Result<T, MaskerError>;
@@ -1,21 +1,25 @@
 use fake::faker::address::raw::*;
 use fake::faker::internet::raw::*;
 use fake::faker::name::raw::*;
-use fake::faker::number;
+use fake::faker::number::raw::*;
 use fake::faker::phone_number::raw::*;
 use fake::Fake;
 
 use ordered_float::OrderedFloat;
 
 use fake::locales::*;
-use fakeit::{address, payment, person};
+use fakeit::data::{address, payment};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::str::FromStr;
 
 use crate::MResult;
 use crate::MaskerError;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Category {
     Name,
     FirstName,
@@ -52,6 +56,8 @@ const CATEGORIES: &[&'static str] = &[
 pub struct WordClassification {
     pub category: Category,
     pub similar: Vec<String>,
+    /// The mean similarity of the word to each category, best match first.
+    pub scores: Vec<(Category, f64)>,
 }
 
 impl std::str::FromStr for Category {
@@ -75,6 +81,60 @@ impl std::str::FromStr for Category {
     }
 }
 
+impl std::fmt::Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Category::Name => write!(f, "name"),
+            Category::FirstName => write!(f, "first_name"),
+            Category::LastName => write!(f, "last_name"),
+            Category::Email => write!(f, "email"),
+            Category::Address => write!(f, "address"),
+            Category::Ssn => write!(f, "ssn"),
+            Category::City => write!(f, "city"),
+            Category::PhoneNumber => write!(f, "phone_number"),
+            Category::CreditCard => write!(f, "credit_card"),
+            Category::ZipCode => write!(f, "zip_code"),
+            Category::PositiveDecimal => write!(f, "positive_decimal"),
+            Category::Inferred => write!(f, "inferred"),
+        }
+    }
+}
+
+/// Tells whether `word` is recognizably not real data for `category`: blank or
+/// repeated-character placeholders, reserved example domains, SSNs that are never
+/// issued, fictional 555 phone numbers and card numbers failing the Luhn check.
+pub fn looks_synthetic(word: &str, category: Category) -> bool {
+    let word = word.trim();
+    let significant: Vec<char> = word.chars().filter(|c| c.is_alphanumeric()).collect();
+    if significant.is_empty() || significant.iter().all(|c| *c == significant[0]) {
+        return true;
+    }
+    let digits: String = significant.iter().filter(|c| c.is_ascii_digit()).collect();
+    match category {
+        Category::Email => {
+            let domain = word.rsplit('@').next().unwrap_or_default().to_lowercase();
+            domain.starts_with("example.")
+                || [".example", ".test", ".invalid", ".localhost"]
+                    .iter()
+                    .any(|tld| domain.ends_with(tld))
+        }
+        Category::Ssn if digits.len() == 9 => {
+            let (area, rest) = digits.split_at(3);
+            let (group, serial) = rest.split_at(2);
+            area == "000"
+                || area == "666"
+                || area.starts_with('9')
+                || group == "00"
+                || serial == "0000"
+        }
+        Category::PhoneNumber => {
+            digits.len() >= 10 && digits[digits.len() - 7..].starts_with("555")
+        }
+        Category::CreditCard if digits.len() >= 12 => !crate::format::luhn_valid(&digits),
+        _ => false,
+    }
+}
+
 pub fn sample_similar_word_for_category(
     word: &str,
     category: Category,
@@ -161,26 +221,90 @@ pub fn classify_word(
     Ok(WordClassification {
         category: Category::from_str(means[0].1).unwrap(),
         similar: means[0].2.iter().map(|x| x.1.clone()).collect(),
+        scores: means
+            .iter()
+            .map(|x| (Category::from_str(x.1).unwrap(), x.0))
+            .collect(),
     })
 }
 
+/// The locales fake words can be generated in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    FrFr,
+    PtBr,
+    ZhCn,
+    ZhTw,
+    JaJp,
+    ArSa,
+}
+
+impl std::str::FromStr for Locale {
+    type Err = MaskerError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "en" => Ok(Locale::En),
+            "fr_fr" => Ok(Locale::FrFr),
+            "pt_br" => Ok(Locale::PtBr),
+            "zh_cn" => Ok(Locale::ZhCn),
+            "zh_tw" => Ok(Locale::ZhTw),
+            "ja_jp" => Ok(Locale::JaJp),
+            "ar_sa" => Ok(Locale::ArSa),
+            _ => Err(MaskerError::SimilarityError(format!(
+                "Invalid locale: {}",
+                s
+            ))),
+        }
+    }
+}
+
 pub fn generate_fake_words_pool(category_pool_size: usize) -> FakeWordPool {
+    generate_fake_words_pool_with_rng(category_pool_size, Locale::En, &mut rand::thread_rng())
+}
+
+/// Generates the pool in `locale`, drawing every word from `rng`. A seeded `rng`
+/// always yields the same pool.
+pub fn generate_fake_words_pool_with_rng<R: Rng + ?Sized>(
+    category_pool_size: usize,
+    locale: Locale,
+    rng: &mut R,
+) -> FakeWordPool {
+    match locale {
+        Locale::En => fill_fake_words_pool(EN, category_pool_size, rng),
+        Locale::FrFr => fill_fake_words_pool(FR_FR, category_pool_size, rng),
+        Locale::PtBr => fill_fake_words_pool(PT_BR, category_pool_size, rng),
+        Locale::ZhCn => fill_fake_words_pool(ZH_CN, category_pool_size, rng),
+        Locale::ZhTw => fill_fake_words_pool(ZH_TW, category_pool_size, rng),
+        Locale::JaJp => fill_fake_words_pool(JA_JP, category_pool_size, rng),
+        Locale::ArSa => fill_fake_words_pool(AR_SA, category_pool_size, rng),
+    }
+}
+
+fn fill_fake_words_pool<L: Data + Copy, R: Rng + ?Sized>(
+    locale: L,
+    category_pool_size: usize,
+    rng: &mut R,
+) -> FakeWordPool {
     let mut data: FakeWordPool = Vec::new();
     (0..CATEGORIES.len()).for_each(|i| {
         let mut row = vec![];
         for _ in 0..category_pool_size {
             match Category::from_str(CATEGORIES[i]).unwrap() {
-                Category::Name => row.push(Name(EN).fake()),
-                Category::FirstName => row.push(FirstName(EN).fake()),
-                Category::LastName => row.push(LastName(EN).fake()),
-                Category::Email => row.push(SafeEmail(EN).fake()),
-                Category::Address => row.push(address::street()),
-                Category::Ssn => row.push(person::ssn()),
-                Category::City => row.push(CityName(EN).fake()),
-                Category::PhoneNumber => row.push(PhoneNumber(EN).fake()),
-                Category::CreditCard => row.push(payment::credit_card_number()),
-                Category::ZipCode => row.push(address::zip()),
-                Category::PositiveDecimal => row.push(number::en::NumberWithFormat("####.##").fake()),
+                Category::Name => row.push(Name(locale).fake_with_rng(rng)),
+                Category::FirstName => row.push(FirstName(locale).fake_with_rng(rng)),
+                Category::LastName => row.push(LastName(locale).fake_with_rng(rng)),
+                Category::Email => row.push(SafeEmail(locale).fake_with_rng(rng)),
+                Category::Address => row.push(fakeit_street(rng)),
+                Category::Ssn => row.push(fakeit_ssn(rng)),
+                Category::City => row.push(CityName(locale).fake_with_rng(rng)),
+                Category::PhoneNumber => row.push(PhoneNumber(locale).fake_with_rng(rng)),
+                Category::CreditCard => row.push(fakeit_pattern(payment::NUMBER, rng)),
+                Category::ZipCode => row.push(fakeit_pattern(address::ZIP, rng)),
+                Category::PositiveDecimal => {
+                    row.push(NumberWithFormat(locale, "####.##").fake_with_rng(rng))
+                }
                 Category::Inferred => {}
             }
         }
@@ -191,6 +315,48 @@ pub fn generate_fake_words_pool(category_pool_size: usize) -> FakeWordPool {
     data
 }
 
+// The addresses, SSNs, card numbers and zip codes are drawn like `fakeit` draws them,
+// from its data, but with `rng`: its own generators cannot be seeded.
+
+/// A street as drawn by `fakeit::address::street`.
+fn fakeit_street<R: Rng + ?Sized>(rng: &mut R) -> String {
+    let number = fakeit_pattern(address::NUMBER, rng);
+    let prefix = pick(address::STREET_PREFIX, rng);
+    let name = pick(address::STATE, rng);
+    let suffix = pick(address::STREET_SUFFIX, rng);
+    if rng.gen_bool(0.5) {
+        format!("{} {} {} {}", number, prefix, name, suffix)
+    } else {
+        format!("{} {} {}", number, name, suffix)
+    }
+}
+
+/// A nine-digit SSN as drawn by `fakeit::person::ssn`, redrawn until it could be
+/// issued, as the format-preserving SSNs are.
+fn fakeit_ssn<R: Rng + ?Sized>(rng: &mut R) -> String {
+    loop {
+        let ssn = rng.gen_range(100000000..999999999).to_string();
+        if crate::format::is_valid(&ssn, Category::Ssn) {
+            return ssn;
+        }
+    }
+}
+
+/// One of the `patterns`, its `#` replaced with random digits.
+fn fakeit_pattern<R: Rng + ?Sized>(patterns: &[&str], rng: &mut R) -> String {
+    pick(patterns, rng)
+        .chars()
+        .map(|c| match c {
+            '#' => char::from(b'0' + rng.gen_range(0..10)),
+            c => c,
+        })
+        .collect()
+}
+
+fn pick<'a, R: Rng + ?Sized>(words: &[&'a str], rng: &mut R) -> &'a str {
+    words.choose(rng).copied().unwrap_or_default()
+}
+
 mod tests {
     #![allow(unused_imports)]
     use super::*;
@@ -205,6 +371,46 @@ mod tests {
         assert_eq!(result.len() > 0, true);
     }
 
+    #[test]
+    fn test_generate_seeded_fake_words_pool() {
+        use rand::SeedableRng;
+
+        let pool = |seed| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            generate_fake_words_pool_with_rng(10, Locale::FrFr, &mut rng)
+        };
+        assert_eq!(pool(42), pool(42));
+        assert_ne!(pool(42), pool(43));
+        assert_eq!(pool(42).len(), CATEGORIES.len());
+
+        let words = |category| pool(42).into_iter().find(|x| x.0 == category).unwrap().1;
+        for ssn in words(Category::Ssn) {
+            assert!(crate::format::is_valid(&ssn, Category::Ssn), "{}", ssn);
+        }
+        for card in words(Category::CreditCard) {
+            assert!(card.chars().all(|c| c.is_ascii_digit()), "{}", card);
+        }
+    }
+
+    #[test]
+    fn test_looks_synthetic() {
+        assert!(looks_synthetic("", Category::FirstName));
+        assert!(looks_synthetic("XXXX", Category::LastName));
+        assert!(looks_synthetic("000-00-0000", Category::Ssn));
+        assert!(looks_synthetic("666-12-3456", Category::Ssn));
+        assert!(looks_synthetic("john@example.com", Category::Email));
+        assert!(looks_synthetic("310-555-0134", Category::PhoneNumber));
+        assert!(looks_synthetic("4111 1111 1111 1112", Category::CreditCard));
+        assert!(!looks_synthetic("John", Category::FirstName));
+        assert!(!looks_synthetic("123-45-6789", Category::Ssn));
+        assert!(!looks_synthetic("susan@gmail.com", Category::Email));
+        assert!(!looks_synthetic("310-333-2132", Category::PhoneNumber));
+        assert!(!looks_synthetic(
+            "4111 1111 1111 1111",
+            Category::CreditCard
+        ));
+    }
+
     #[test]
     fn test_classify_word() {
         let generated_pool = generate_fake_words_pool(10000);
@@ -223,5 +429,7 @@ mod tests {
             0
         );
         assert_eq!(result.as_ref().unwrap().similar.len() > 0, true);
+        assert_eq!(result.as_ref().unwrap().scores.len(), CATEGORIES.len());
+        assert_eq!(result.as_ref().unwrap().scores[0].0, Category::Email);
     }
 }